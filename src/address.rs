@@ -0,0 +1,190 @@
+//! Addresses as shown to people: Bech32 (BIP173) strings with a human-readable part naming the
+//! network and a checksum catching typos, like `ebc1qw508d6qejxtdg4y5r3zarvary0c5xw7k6awdyv`.
+//! The prefixes are this chain's own, so that no address is also valid on Bitcoin.
+//! The data part is a version (0) followed by the 20-byte address hash.
+use serde::{Serialize, Serializer};
+use std::convert::TryInto;
+use crate::crypto::hash::H160;
+use crate::params::{ChainParams, Network};
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+const MAX_LENGTH: usize = 90;
+const VERSION: u8 = 0;
+
+/// An address hash and the network it is meant for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub network: Network,
+    pub hash: H160,
+}
+
+impl Address {
+    pub fn new(network: Network, hash: H160) -> Self {
+        Address { network, hash }
+    }
+
+    /// Read the address hash out of an address, which must be for `network`.
+    pub fn parse_for(s: &str, network: Network) -> Result<H160, String> {
+        let address: Address = s.parse()?;
+        if address.network != network {
+            return Err(format!("{} is a {} address, not a {} one", s, address.network, network));
+        }
+        Ok(address.hash)
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut data = vec![VERSION];
+        data.extend(convert_bits(self.hash.as_ref(), 8, 5, true).unwrap());
+        write!(f, "{}", encode(ChainParams::for_network(self.network).address_hrp, &data))
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hrp, data) = decode(s)?;
+        let network = [Network::Mainnet, Network::Testnet, Network::Regtest].iter()
+            .find(|network| ChainParams::for_network(**network).address_hrp == hrp)
+            .ok_or_else(|| format!("unknown address prefix {}", hrp))?;
+        match data.split_first() {
+            Some((&VERSION, program)) => {
+                let bytes = convert_bits(program, 5, 8, false)?;
+                let hash: [u8; 20] = bytes.as_slice().try_into()
+                    .map_err(|_| format!("address of {} bytes instead of 20", bytes.len()))?;
+                Ok(Address::new(*network, hash.into()))
+            }
+            Some((version, _)) => Err(format!("unknown address version {}", version)),
+            None => Err("empty address".to_string()),
+        }
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = (chk & 0x1ff_ffff) << 5 ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+/// Bech32 string of `hrp` and 5-bit `data`.
+fn encode(hrp: &str, data: &[u8]) -> String {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; 6]);
+    let checksum = polymod(&values) ^ 1;
+    let mut encoded = format!("{}1", hrp);
+    for value in data.iter().cloned().chain((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 31) as u8)) {
+        encoded.push(CHARSET[value as usize] as char);
+    }
+    encoded
+}
+
+/// Human-readable part and 5-bit data of a Bech32 string, checksum removed.
+fn decode(s: &str) -> Result<(String, Vec<u8>), String> {
+    if s.len() > MAX_LENGTH {
+        return Err("address too long".to_string());
+    }
+    if s.to_lowercase() != s && s.to_uppercase() != s {
+        return Err("address mixes upper and lower case".to_string());
+    }
+    let s = s.to_lowercase();
+    let separator = s.rfind('1').ok_or("address without separator")?;
+    let (hrp, data) = (&s[..separator], &s[separator + 1..]);
+    if hrp.is_empty() || data.len() < 6 || hrp.bytes().any(|c| !(33..=126).contains(&c)) {
+        return Err("malformed address".to_string());
+    }
+    let data = data.bytes()
+        .map(|c| CHARSET.iter().position(|x| *x == c).map(|i| i as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or("invalid character in address")?;
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if polymod(&values) != 1 {
+        return Err("invalid address checksum".to_string());
+    }
+    Ok((hrp.to_string(), data[..data.len() - 6].to_vec()))
+}
+
+/// Regroup `data` from `from`-bit to `to`-bit values. Without `pad`, leftover bits must be
+/// fewer than `from` and zero.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, String> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let mut out = Vec::new();
+    let max = (1 << to) - 1;
+    for value in data {
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return Err("invalid padding in address".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bech32_checksums() {
+        for valid in ["A12UEL5L", "a12uel5l", "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+                      "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w"] {
+            assert!(decode(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["a12uel5m", "A12uEL5L", "pzry9x0s0muk", "1pzry9x0s0muk", "x1b4n0q5v", "li1dgmt3"] {
+            assert!(decode(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn address_round_trip() {
+        let hash: H160 = "751e76e8199196d454941c45d1b3a323f1433bd6".parse().unwrap();
+        let address = Address::new(Network::Mainnet, hash);
+        assert_eq!(address.to_string(), "ebc1qw508d6qejxtdg4y5r3zarvary0c5xw7k6awdyv");
+        assert_eq!("EBC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7K6AWDYV".parse::<Address>().unwrap(), address);
+        // the same hash as a Bitcoin address is not one of ours
+        assert!("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".parse::<Address>().is_err());
+        for network in [Network::Testnet, Network::Regtest] {
+            let other = Address::new(network, hash);
+            assert_eq!(other.to_string().parse::<Address>().unwrap(), other);
+            assert!(Address::parse_for(&other.to_string(), Network::Mainnet).is_err());
+        }
+        assert_eq!(Address::parse_for(&address.to_string(), Network::Mainnet).unwrap(), hash);
+        // a typo
+        assert!("ebc1qw508d6qejxtdg4y5r3zarvary0c5xw7k6awdyw".parse::<Address>().is_err());
+        assert!(hash.to_string().parse::<Address>().is_err());
+    }
+}
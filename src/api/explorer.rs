@@ -0,0 +1,155 @@
+//! Block explorer: a static page at `/explorer` and the JSON it reads, over the chain data.
+use serde::Serialize;
+use crate::address::Address;
+use crate::blockchain::Blockchain;
+use crate::crypto::hash::{H160, H256, Hashable};
+use super::{BlockResponse, TxResponse};
+
+/// Blocks listed by `/explorer/blocks` unless asked otherwise
+pub const DEFAULT_BLOCK_COUNT: usize = 20;
+/// Most blocks listed by `/explorer/blocks`
+pub const MAX_BLOCK_COUNT: usize = 100;
+
+/// The page and its script, served as they are
+pub fn asset(path: &str) -> Option<(&'static str, &'static str)> {
+    match path {
+        "/explorer" | "/explorer/" => Some(("text/html; charset=utf-8", include_str!("explorer/index.html"))),
+        "/explorer/explorer.js" => Some(("application/javascript", include_str!("explorer/explorer.js"))),
+        _ => None,
+    }
+}
+
+#[derive(Serialize)]
+pub struct BlockSummary {
+    hash: String,
+    height: u32,
+    parent: String,
+    timestamp: u128,
+    transactions: usize,
+}
+
+impl BlockSummary {
+    fn new(bc: &Blockchain, hash: &H256) -> Self {
+        let (block, height) = &bc.blocks[hash];
+        BlockSummary {
+            hash: hash.to_string(),
+            height: *height,
+            parent: block.header.parent.to_string(),
+            timestamp: block.header.get_create_time(),
+            transactions: block.content.len(),
+        }
+    }
+}
+
+/// A branch off the longest chain
+#[derive(Serialize)]
+pub struct ForkResponse {
+    /// Last block shared with the longest chain
+    fork_point: String,
+    fork_height: u32,
+    /// From the block after the fork point to the tip of the branch
+    blocks: Vec<BlockSummary>,
+}
+
+#[derive(Serialize)]
+pub struct BlockDetail {
+    block: BlockResponse,
+    transactions: Vec<TxResponse>,
+}
+
+#[derive(Serialize)]
+pub struct AddressDetail {
+    address: Address,
+    balance: u64,
+    /// Transactions of the longest chain paying or spending from the address, latest first
+    transactions: Vec<TxResponse>,
+}
+
+/// Up to `count` blocks of the longest chain, from height `start` (the tip by default) down.
+pub fn recent_blocks(bc: &Blockchain, start: Option<u32>, count: usize) -> Vec<BlockSummary> {
+    let start = start.unwrap_or_else(|| bc.get_length());
+    bc.all_blocks_in_longest_chain().iter()
+        .rev()
+        .filter(|hash| bc.blocks[*hash].1 <= start)
+        .take(count.min(MAX_BLOCK_COUNT))
+        .map(|hash| BlockSummary::new(bc, hash))
+        .collect()
+}
+
+pub fn forks(bc: &Blockchain) -> Vec<ForkResponse> {
+    bc.forks().iter()
+        .map(|branch| {
+            let fork_point = bc.blocks[&branch[0]].0.header.parent;
+            ForkResponse {
+                fork_point: fork_point.to_string(),
+                fork_height: bc.blocks[&fork_point].1,
+                blocks: branch.iter().map(|hash| BlockSummary::new(bc, hash)).collect(),
+            }
+        })
+        .collect()
+}
+
+/// The block `hash` with its transactions.
+pub fn block(bc: &Blockchain, hash: &H256) -> Option<BlockDetail> {
+    let block = BlockResponse::new(bc, hash)?;
+    let (content, height) = &bc.blocks[hash];
+    let in_longest_chain = bc.contain(*hash);
+    let network = bc.params().network;
+    let transactions = content.content.iter()
+        .map(|tx| TxResponse::new(tx, Some((*hash, *height)).filter(|_| in_longest_chain), network))
+        .collect();
+    Some(BlockDetail { block, transactions })
+}
+
+pub fn address(bc: &Blockchain, address: &H160) -> AddressDetail {
+    let network = bc.params().network;
+    let transactions = bc.address_transactions(&[*address]).iter()
+        .rev()
+        .map(|(tx, proof)| TxResponse::new(tx, Some((proof.header.hash(), proof.header.get_height())), network))
+        .collect();
+    AddressDetail {
+        address: Address::new(network, *address),
+        balance: bc.balance(address),
+        transactions,
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::block::generate_random_block;
+
+    #[test]
+    fn recent_blocks_and_forks() {
+        let mut bc = Blockchain::new();
+        let genesis = bc.tip();
+        let mut parent = genesis;
+        let mut main = Vec::new();
+        for _ in 0..5 {
+            let block = generate_random_block(&parent);
+            bc.insert(&block);
+            parent = block.hash();
+            main.push(parent);
+        }
+        let fork = generate_random_block(&main[1]);
+        bc.insert(&fork);
+
+        let recent = recent_blocks(&bc, None, 3);
+        let heights: Vec<u32> = recent.iter().map(|b| b.height).collect();
+        assert_eq!(heights, vec![5, 4, 3]);
+        assert_eq!(recent[0].hash, main[4].to_string());
+        let heights: Vec<u32> = recent_blocks(&bc, Some(2), 10).iter().map(|b| b.height).collect();
+        assert_eq!(heights, vec![2, 1, 0]);
+
+        let forks = forks(&bc);
+        assert_eq!(forks.len(), 1);
+        assert_eq!((forks[0].fork_point.clone(), forks[0].fork_height), (main[1].to_string(), 2));
+        assert_eq!(forks[0].blocks[0].hash, fork.hash().to_string());
+
+        let detail = block(&bc, &fork.hash()).unwrap();
+        assert!(!detail.block.in_longest_chain);
+        assert_eq!(detail.transactions.len(), fork.content.len());
+        assert!(detail.transactions.iter().all(|tx| tx.block.is_none()));
+        assert!(block(&bc, &H256::from([7u8; 32])).is_none());
+    }
+}
@@ -0,0 +1,157 @@
+// Renders the pages of the explorer from the JSON endpoints of the API server. Pages are
+// addressed by the URL fragment: #/, #/block/<hash>, #/tx/<txid> and #/address/<address>.
+"use strict";
+
+const main = document.getElementById("main");
+
+async function get(path) {
+  const reply = await (await fetch(path)).json();
+  if (reply && reply.success === false) {
+    throw new Error(reply.message);
+  }
+  return reply;
+}
+
+function el(tag, attrs, ...children) {
+  const node = document.createElement(tag);
+  Object.entries(attrs || {}).forEach(([k, v]) => node.setAttribute(k, v));
+  children.flat(Infinity).forEach(child => node.append(child instanceof Node ? child : String(child)));
+  return node;
+}
+
+function link(kind, id, text) {
+  return el("a", { href: `#/${kind}/${id}` }, text || id);
+}
+
+function time(ms) {
+  return new Date(Number(ms)).toISOString().replace("T", " ").replace(/\..*/, "");
+}
+
+function table(headers, rows) {
+  return el("table", {}, el("tr", {}, headers.map(h => el("th", {}, h))),
+    rows.map(row => el("tr", {}, row.map(cell => el("td", {}, cell)))));
+}
+
+function fields(pairs) {
+  return el("dl", {}, pairs.map(([k, v]) => [el("dt", {}, k), el("dd", {}, v)]));
+}
+
+function blockRows(blocks) {
+  return table(["Height", "Hash", "Time", "Transactions"],
+    blocks.map(b => [b.height, link("block", b.hash), time(b.timestamp), b.transactions]));
+}
+
+function txTable(txs) {
+  return table(["Transaction", "Block", "Inputs", "Outputs"], txs.map(tx => [
+    link("tx", tx.txid, tx.txid.slice(0, 16) + "…"),
+    tx.block ? link("block", tx.block, tx.height) : el("span", { class: "muted" }, "pending"),
+    tx.inputs.map(i => `${i.value} from ${i.previous_hash.slice(0, 12)}…`).join(", "),
+    el("span", {}, tx.outputs.map((o, n) => [n ? ", " : "", `${o.balance} to `, link("address", o.address)])),
+  ]));
+}
+
+async function home(start) {
+  const query = start === undefined ? "" : `&start=${start}`;
+  const [blocks, forks] = await Promise.all([get(`/explorer/blocks?count=20${query}`), get("/explorer/forks")]);
+  const page = [el("h2", {}, "Recent blocks"), blockRows(blocks)];
+  const last = blocks[blocks.length - 1];
+  if (last && last.height > 0) {
+    const older = el("button", {}, "Older blocks");
+    older.onclick = () => show(home(last.height - 1));
+    page.push(older);
+  }
+  page.push(el("h2", {}, "Forks"));
+  if (forks.length === 0) {
+    page.push(el("p", { class: "muted" }, "No branch off the longest chain."));
+  }
+  forks.forEach(fork => page.push(
+    el("h3", {}, "From ", link("block", fork.fork_point, `height ${fork.fork_height}`)),
+    blockRows(fork.blocks)));
+  return page;
+}
+
+async function block(hash) {
+  const { block, transactions } = await get(`/explorer/block/${hash}`);
+  return [
+    el("h2", {}, `Block ${block.height}`),
+    fields([
+      ["Hash", block.hash],
+      ["Parent", link("block", block.parent)],
+      ["Time", time(block.timestamp)],
+      ["Longest chain", block.in_longest_chain ? "yes" : "no"],
+      ["Version", block.version.toString(16)],
+      ["Nonce", block.nonce],
+      ["Difficulty", block.difficulty],
+      ["Merkle root", block.merkle_root],
+    ]),
+    el("h3", {}, "Transactions"),
+    txTable(transactions),
+  ];
+}
+
+async function tx(txid) {
+  const tx = await get(`/tx/${txid}`);
+  return [
+    el("h2", {}, "Transaction"),
+    fields([
+      ["Hash", tx.txid],
+      ["Id", tx.id],
+      ["Block", tx.block ? link("block", tx.block, `${tx.height} (${tx.block})`) : "pending"],
+    ]),
+    txTable([tx]),
+  ];
+}
+
+async function address(addr) {
+  const detail = await get(`/explorer/address/${addr}`);
+  return [
+    el("h2", {}, "Address"),
+    fields([["Address", detail.address], ["Balance", detail.balance]]),
+    el("h3", {}, "Transactions"),
+    txTable(detail.transactions),
+  ];
+}
+
+async function show(page) {
+  try {
+    main.replaceChildren(...await page);
+  } catch (e) {
+    main.replaceChildren(el("p", { class: "error" }, e.message));
+  }
+}
+
+function route() {
+  const [, kind, id] = location.hash.split("/");
+  const pages = { block, tx, address };
+  show(pages[kind] && id ? pages[kind](id) : home());
+}
+
+document.getElementById("search").onsubmit = event => {
+  event.preventDefault();
+  const q = event.target.q.value.trim();
+  // hashes are hex; anything else is taken for an address
+  if (/^[0-9a-f]{64}$/i.test(q)) {
+    get(`/tx/${q}`).then(() => { location.hash = `#/tx/${q}`; }, () => { location.hash = `#/block/${q}`; });
+  } else if (q) {
+    location.hash = `#/address/${q}`;
+  }
+};
+
+async function poll() {
+  try {
+    const info = await get("/chain/info");
+    document.getElementById("status").textContent =
+      `${info.network} · height ${info.height} · mempool ${info.mempool_size}`;
+  } catch (e) {
+    document.getElementById("status").textContent = "node unreachable";
+  }
+}
+
+window.onhashchange = route;
+route();
+poll();
+// refresh the status line as the chain and the mempool change
+const events = new EventSource("/events");
+["block_connected", "block_disconnected", "tx_added_to_mempool", "tx_removed"]
+  .forEach(type => events.addEventListener(type, poll));
+events.onerror = () => { document.getElementById("status").textContent = "node unreachable"; };
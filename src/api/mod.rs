@@ -1,22 +1,196 @@
-use serde::Serialize;
-use crate::miner::Handle as MinerHandle;
+use serde::{Deserialize, Serialize};
+use crate::address::Address;
+use crate::block::Header as BlockHeader;
+use crate::blockchain::Blockchain;
+use crate::crypto::hash::{H256, Hashable};
+use crate::crypto::sigcache::SignatureCache;
+use crate::encoding::{Decode, Encode};
+use crate::events::{EventBus, EventKind, CLIENT_BACKLOG};
+use crate::mempool::Mempool;
+use crate::metrics::Metrics;
+use crate::params::Network;
+use crate::signedtrans::SignedTrans;
+use crate::wallet::{HdWallet, Keystore};
+use crate::miner::{Handle as MinerHandle, MiningLimit};
 use crate::network::server::Handle as NetworkServerHandle;
 use crate::network::message::Message;
 use crate::generator::Generator;
 
-use log::info;
+use crossbeam::channel::{self, Sender};
+use log::{error, info};
 use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use tiny_http::Header;
+use tiny_http::Method;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
 use url::Url;
 
+mod explorer;
+
+/// Longest silence on an event stream
+const EVENT_KEEPALIVE: Duration = Duration::from_secs(15);
+
 pub struct Server {
     handle: HTTPServer,
-    miner: MinerHandle,
-    generator: Generator,
-    network: NetworkServerHandle,
+    context: Context,
+}
+
+/// The parts of the node the API server reaches, handed to every request.
+#[derive(Clone)]
+pub struct Context {
+    pub miner: MinerHandle,
+    pub generator: Generator,
+    pub network: NetworkServerHandle,
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub signatures: Arc<SignatureCache>,
+    pub wallet: Arc<Mutex<HdWallet>>,
+    /// File the wallet's seed and handed out addresses are kept in
+    pub keystore: PathBuf,
+    pub events: Arc<EventBus>,
+    pub metrics: Arc<Metrics>,
+    /// `/node/stop` requests are passed on through it
+    pub stop_chan: Sender<()>,
+}
+
+/// Handle to the running API server, used to stop it at shutdown.
+pub struct Handle {
+    running: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Handle {
+    /// Stop accepting requests and wait for the server thread to exit.
+    pub fn stop(self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.thread.join().unwrap();
+    }
+}
+
+#[derive(Serialize)]
+struct BalanceResponse {
+    address: Address,
+    balance: u64,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct AddressResponse {
+    address: Address,
+}
+
+#[derive(Serialize)]
+struct ChainInfoResponse {
+    network: Network,
+    height: u32,
+    tip: String,
+    difficulty: String,
+    median_time_past: u128,
+    mempool_size: usize,
+}
+
+#[derive(Serialize)]
+struct ChainTipResponse {
+    hash: String,
+    height: u32,
+    work: f64,
+    fork_point: String,
+    branch_length: u32,
+    /// `active` for the tip of the longest chain, else `valid-fork`
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct BlockResponse {
+    hash: String,
+    height: u32,
+    version: u32,
+    parent: String,
+    nonce: u32,
+    difficulty: String,
+    timestamp: u128,
+    merkle_root: String,
+    in_longest_chain: bool,
+    transactions: Vec<String>,
+    /// Consensus encoding of the block
+    hex: String,
+}
+
+impl BlockResponse {
+    /// The block `hash`, on the longest chain or not
+    fn new(bc: &Blockchain, hash: &H256) -> Option<Self> {
+        bc.blocks.get(hash).map(|(block, height)| BlockResponse {
+            hash: hash.to_string(),
+            height: *height,
+            version: block.header.get_version(),
+            parent: block.header.parent.to_string(),
+            nonce: block.get_nonce(),
+            difficulty: block.header.difficulty.to_string(),
+            timestamp: block.header.get_create_time(),
+            merkle_root: block.header.get_merkle_root().to_string(),
+            in_longest_chain: bc.contain(*hash),
+            transactions: block.content.iter().map(|tx| tx.hash().to_string()).collect(),
+            hex: hex::encode(block.encode()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TxInputResponse {
+    previous_hash: String,
+    value: u8,
+}
+
+#[derive(Serialize)]
+struct TxOutputResponse {
+    address: Address,
+    balance: u8,
+}
+
+#[derive(Serialize)]
+struct TxResponse {
+    txid: String,
+    id: String,
+    /// Block confirming the transaction on the longest chain, none while in the mempool
+    block: Option<String>,
+    height: Option<u32>,
+    inputs: Vec<TxInputResponse>,
+    outputs: Vec<TxOutputResponse>,
+    /// Consensus encoding of the signed transaction
+    hex: String,
+}
+
+impl TxResponse {
+    fn new(tx: &SignedTrans, block: Option<(H256, u32)>, network: Network) -> Self {
+        TxResponse {
+            txid: tx.hash().to_string(),
+            id: tx.transaction.id.to_string(),
+            block: block.map(|(hash, _)| hash.to_string()),
+            height: block.map(|(_, height)| height),
+            inputs: tx.transaction.inputs.iter()
+                .map(|input| TxInputResponse { previous_hash: input.previous_hash.to_string(), value: input.index })
+                .collect(),
+            outputs: tx.transaction.outputs.iter()
+                .map(|output| TxOutputResponse { address: Address::new(network, output.address), balance: output.balance })
+                .collect(),
+            hex: hex::encode(tx.encode()),
+        }
+    }
+}
+
+/// A transaction with outputs to an address, which a wallet can spend
+#[derive(Serialize)]
+struct UnspentResponse {
+    id: String,
+    value: u64,
+    /// Consensus encoding of the transaction, whose outputs the spending inputs sign
+    hex: String,
 }
 
 #[derive(Serialize)]
@@ -25,6 +199,13 @@ struct ApiResponse {
     message: String,
 }
 
+/// Body of a `/mining/submit` request: a header from `/mining/template` and the nonce solving it.
+#[derive(Deserialize)]
+struct SubmitRequest {
+    header: BlockHeader,
+    nonce: u32,
+}
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -38,25 +219,53 @@ macro_rules! respond_result {
     }};
 }
 
+macro_rules! respond_json {
+    ( $req:expr, $payload:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let resp = Response::from_string(serde_json::to_string_pretty(&$payload).unwrap())
+            .with_header(content_type);
+        $req.respond(resp).unwrap();
+    }};
+}
+
+/// Record the addresses handed out by `wallet` in its keystore.
+fn save_wallet(path: &Path, network: Network, wallet: &HdWallet) -> Result<(), String> {
+    let mut keystore = Keystore::load(path, network)?;
+    keystore.next = wallet.next_indices();
+    keystore.save(path)
+}
+
 impl Server {
-    pub fn start(
-        addr: std::net::SocketAddr,
-        miner: &MinerHandle,
-        generator: &Generator,
-        network: &NetworkServerHandle,
-    ) {
+    /// Start the API server.
+    pub fn start(addr: std::net::SocketAddr, context: Context) -> Handle {
         let handle = HTTPServer::http(&addr).unwrap();
-        let server = Self {
-            handle,
-            miner: miner.clone(),
-            generator: generator.clone(),
-            network: network.clone(),
-        };
-        thread::spawn(move || {
-            for req in server.handle.incoming_requests() {
-                let miner = server.miner.clone();
-                let network = server.network.clone();
-                let generator = server.generator.clone();
+        let server = Self { handle, context };
+        let running = Arc::new(AtomicBool::new(true));
+        let still_running = Arc::clone(&running);
+        let thread = thread::spawn(move || {
+            while still_running.load(Ordering::SeqCst) {
+                // wake up regularly to notice a shutdown
+                let mut req = match server.handle.recv_timeout(Duration::from_millis(100)) {
+                    Ok(Some(req)) => req,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("API server error: {}", e);
+                        break;
+                    }
+                };
+                let Context {
+                    miner,
+                    generator,
+                    network,
+                    blockchain,
+                    mempool,
+                    signatures,
+                    wallet,
+                    keystore,
+                    events,
+                    metrics,
+                    stop_chan,
+                } = server.context.clone();
                 thread::spawn(move || {
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
@@ -67,6 +276,12 @@ impl Server {
                             return;
                         }
                     };
+                    if let Some((content_type, body)) = explorer::asset(url.path()) {
+                        let content_type = format!("Content-Type: {}", content_type).parse::<Header>().unwrap();
+                        // from_string would add a text/plain content type of its own
+                        req.respond(Response::from_data(body).with_header(content_type)).unwrap();
+                        return;
+                    }
                     match url.path() {
                         "/miner/start" => {
                             let params = url.query_pairs();
@@ -89,13 +304,271 @@ impl Server {
                                     return;
                                 }
                             };
-                            miner.start(lambda);
+                            let mut limit = MiningLimit::default();
+                            if let Some(v) = params.get("duration") {
+                                match v.parse::<u64>() {
+                                    Ok(secs) => limit.duration = Some(Duration::from_secs(secs)),
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing duration: {}", e)
+                                        );
+                                        return;
+                                    }
+                                }
+                            }
+                            if let Some(v) = params.get("blocks") {
+                                match v.parse::<u32>() {
+                                    Ok(n) => limit.blocks = Some(n),
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing blocks: {}", e)
+                                        );
+                                        return;
+                                    }
+                                }
+                            }
+                            miner.start(lambda, limit);
+                            respond_result!(req, true, "ok");
+                        }
+                        "/miner/pause" => {
+                            miner.pause();
+                            respond_result!(req, true, "ok");
+                        }
+                        "/miner/resume" => {
+                            miner.resume();
                             respond_result!(req, true, "ok");
                         }
-                        "/miner/end" => {
-                            miner.exit();
+                        "/miner/stop" | "/miner/end" => {
+                            miner.stop();
                             respond_result!(req, true, "ok");
                         }
+                        "/miner/status" => {
+                            respond_json!(req, miner.status());
+                        }
+                        "/wallet/receive" | "/wallet/change" => {
+                            if req.method() != &Method::Post {
+                                respond_result!(req, false, "handing out an address requires POST");
+                                return;
+                            }
+                            let network = blockchain.lock().unwrap().params().network;
+                            let mut wallet = wallet.lock().unwrap();
+                            let address = if url.path() == "/wallet/receive" {
+                                wallet.receive_address()
+                            } else {
+                                wallet.change_address()
+                            };
+                            // a restart must not hand the address out again
+                            if let Err(e) = save_wallet(&keystore, network, &wallet) {
+                                respond_result!(req, false, e);
+                                return;
+                            }
+                            drop(wallet);
+                            respond_json!(req, AddressResponse { address: Address::new(network, address) });
+                        }
+                        "/chain/info" => {
+                            let pool_size = mempool.lock().unwrap().pool.len();
+                            let bc = blockchain.lock().unwrap();
+                            let payload = ChainInfoResponse {
+                                network: bc.params().network,
+                                height: bc.get_length(),
+                                tip: bc.tip().to_string(),
+                                difficulty: bc.get_difficulty().to_string(),
+                                median_time_past: bc.median_time_past(&bc.tip()),
+                                mempool_size: pool_size,
+                            };
+                            drop(bc);
+                            respond_json!(req, payload);
+                        }
+                        "/chain/tips" => {
+                            let tips: Vec<ChainTipResponse> = blockchain.lock().unwrap().tips().iter()
+                                .map(|tip| ChainTipResponse {
+                                    hash: tip.hash.to_string(),
+                                    height: tip.height,
+                                    work: tip.work,
+                                    fork_point: tip.fork_point.to_string(),
+                                    branch_length: tip.branch_length,
+                                    status: if tip.active { "active" } else { "valid-fork" },
+                                })
+                                .collect();
+                            respond_json!(req, tips);
+                        }
+                        "/mempool" => {
+                            let txids: Vec<String> = mempool.lock().unwrap().pool.keys()
+                                .map(|h| h.to_string())
+                                .collect();
+                            respond_json!(req, txids);
+                        }
+                        "/tx/submit" => {
+                            if req.method() != &Method::Post {
+                                respond_result!(req, false, "submit requires POST");
+                                return;
+                            }
+                            let mut body = String::new();
+                            if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                                respond_result!(req, false, format!("error reading body: {}", e));
+                                return;
+                            }
+                            let tx = match hex::decode(body.trim()).map_err(|e| e.to_string())
+                                .and_then(|bytes| SignedTrans::decode(&bytes)) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing transaction: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let mut bc = blockchain.lock().unwrap();
+                            let result = mempool.lock().unwrap().accept(&mut bc, &tx, &*signatures);
+                            drop(bc);
+                            match result {
+                                Ok(()) => respond_result!(req, true, tx.hash()),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/explorer/blocks" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let count = match params.get("count").map(|v| v.parse::<usize>()) {
+                                Some(Ok(v)) => v,
+                                Some(Err(e)) => {
+                                    respond_result!(req, false, format!("error parsing count: {}", e));
+                                    return;
+                                }
+                                None => explorer::DEFAULT_BLOCK_COUNT,
+                            };
+                            let start = match params.get("start").map(|v| v.parse::<u32>()).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, format!("error parsing start: {}", e));
+                                    return;
+                                }
+                            };
+                            let blocks = explorer::recent_blocks(&blockchain.lock().unwrap(), start, count);
+                            respond_json!(req, blocks);
+                        }
+                        "/explorer/forks" => {
+                            let forks = explorer::forks(&blockchain.lock().unwrap());
+                            respond_json!(req, forks);
+                        }
+                        "/events" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            // every type unless only some are asked for
+                            let kinds = match params.get("types") {
+                                Some(v) => v.split(',').map(str::parse).collect::<Result<Vec<EventKind>, _>>(),
+                                None => Ok(EventKind::ALL.to_vec()),
+                            };
+                            let kinds = match kinds {
+                                Ok(kinds) => kinds,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let received = events.subscribe_bounded(&kinds, CLIENT_BACKLOG);
+                            // the body is streamed, which tiny_http's chunked writer would buffer
+                            let mut writer = req.into_writer();
+                            let mut stream = |text: String| {
+                                writer.write_all(text.as_bytes()).and_then(|_| writer.flush())
+                            };
+                            let mut open = stream(concat!(
+                                "HTTP/1.1 200 OK\r\n",
+                                "Content-Type: text/event-stream\r\n",
+                                "Cache-Control: no-cache\r\n",
+                                "Connection: close\r\n\r\n",
+                            ).to_string()).is_ok();
+                            while open {
+                                let text = match received.recv_timeout(EVENT_KEEPALIVE) {
+                                    Ok(event) => format!("event: {}\ndata: {}\n\n", event.name(), event.data()),
+                                    // a comment, so that a closed connection is noticed
+                                    Err(channel::RecvTimeoutError::Timeout) => ": keepalive\n\n".to_string(),
+                                    // dropped by the bus for falling behind
+                                    Err(channel::RecvTimeoutError::Disconnected) => break,
+                                };
+                                open = stream(text).is_ok();
+                            }
+                        }
+                        "/metrics" => {
+                            respond_json!(req, metrics.snapshot());
+                        }
+                        "/sigcache/status" => {
+                            respond_json!(req, signatures.stats());
+                        }
+                        "/mining/template" => {
+                            respond_json!(req, miner.template());
+                        }
+                        "/mining/submit" => {
+                            if req.method() != &Method::Post {
+                                respond_result!(req, false, "submit requires POST");
+                                return;
+                            }
+                            let mut body = String::new();
+                            if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                                respond_result!(req, false, format!("error reading body: {}", e));
+                                return;
+                            }
+                            let submission = match serde_json::from_str::<SubmitRequest>(&body) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing submission: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            match miner.submit(submission.header, submission.nonce, &*signatures) {
+                                Ok(hash) => respond_result!(req, true, hash),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/regtest/generate" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let n = match params.get("n").map(|v| v.parse::<usize>()) {
+                                Some(Ok(v)) => v,
+                                Some(Err(e)) => {
+                                    respond_result!(req, false, format!("error parsing n: {}", e));
+                                    return;
+                                }
+                                None => {
+                                    respond_result!(req, false, "missing n");
+                                    return;
+                                }
+                            };
+                            let network = blockchain.lock().unwrap().params().network;
+                            let address = match params.get("address").map(|v| Address::parse_for(v, network)) {
+                                Some(Ok(v)) => v,
+                                Some(Err(e)) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing address: {}", e)
+                                    );
+                                    return;
+                                }
+                                None => {
+                                    respond_result!(req, false, "missing address");
+                                    return;
+                                }
+                            };
+                            match miner.generate(n, &address) {
+                                Ok(hashes) => {
+                                    let hashes: Vec<String> =
+                                        hashes.iter().map(|h| h.to_string()).collect();
+                                    respond_json!(req, hashes);
+                                }
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
                         "/network/ping" => {
                             network.broadcast(Message::Ping(String::from("Test ping")));
                             respond_result!(req, true, "ok");
@@ -128,6 +601,176 @@ impl Server {
                             generator.exit();
                             respond_result!(req, true, "ok");
                         }
+                        "/node/stop" => {
+                            respond_result!(req, true, "shutting down");
+                            let _ = stop_chan.try_send(());
+                        }
+                        path if path.starts_with("/tx/") && path.ends_with("/proof") => {
+                            let id = &path["/tx/".len()..path.len() - "/proof".len()];
+                            let txid = match id.parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing transaction id: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let proof = blockchain.lock().unwrap().tx_proof(&txid);
+                            match proof {
+                                Some(proof) => respond_json!(req, proof),
+                                None => respond_result!(req, false, "transaction not found in the longest chain"),
+                            }
+                        }
+                        path if path.starts_with("/block/") && path.ends_with("/filter") => {
+                            let id = &path["/block/".len()..path.len() - "/filter".len()];
+                            let hash = match id.parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing block hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let filter = blockchain.lock().unwrap().filter(&hash).cloned();
+                            match filter {
+                                Some(filter) => respond_json!(req, filter),
+                                None => respond_result!(req, false, "no filter for this block"),
+                            }
+                        }
+                        path if path.starts_with("/explorer/block/") => {
+                            let hash = match path["/explorer/block/".len()..].parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing block hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let detail = explorer::block(&blockchain.lock().unwrap(), &hash);
+                            match detail {
+                                Some(detail) => respond_json!(req, detail),
+                                None => respond_result!(req, false, "block not found"),
+                            }
+                        }
+                        path if path.starts_with("/explorer/address/") => {
+                            let id = &path["/explorer/address/".len()..];
+                            let network = blockchain.lock().unwrap().params().network;
+                            let address = match Address::parse_for(id, network) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing address: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let detail = explorer::address(&blockchain.lock().unwrap(), &address);
+                            respond_json!(req, detail);
+                        }
+                        path if path.starts_with("/block/") => {
+                            let hash = match path["/block/".len()..].parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing block hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let payload = BlockResponse::new(&blockchain.lock().unwrap(), &hash);
+                            match payload {
+                                Some(payload) => respond_json!(req, payload),
+                                None => respond_result!(req, false, "block not found"),
+                            }
+                        }
+                        path if path.starts_with("/tx/") => {
+                            let txid = match path["/tx/".len()..].parse::<H256>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing transaction id: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let bc = blockchain.lock().unwrap();
+                            let network = bc.params().network;
+                            let confirmed = bc.find_transaction(&txid);
+                            drop(bc);
+                            let payload = match confirmed {
+                                Some((tx, hash, height)) => Some(TxResponse::new(&tx, Some((hash, height)), network)),
+                                None => mempool.lock().unwrap().pool.get(&txid)
+                                    .map(|tx| TxResponse::new(tx, None, network)),
+                            };
+                            match payload {
+                                Some(payload) => respond_json!(req, payload),
+                                None => respond_result!(req, false, "transaction not found"),
+                            }
+                        }
+                        path if path.starts_with("/address/") && path.ends_with("/unspent") => {
+                            let id = &path["/address/".len()..path.len() - "/unspent".len()];
+                            let network = blockchain.lock().unwrap().params().network;
+                            let address = match Address::parse_for(id, network) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing address: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let unspent: Vec<UnspentResponse> = blockchain.lock().unwrap().unspent(&address).iter()
+                                .map(|tx| UnspentResponse {
+                                    id: tx.id.to_string(),
+                                    value: tx.outputs.iter()
+                                        .filter(|out| out.address == address)
+                                        .map(|out| out.balance as u64)
+                                        .sum(),
+                                    hex: hex::encode(tx.encode()),
+                                })
+                                .collect();
+                            respond_json!(req, unspent);
+                        }
+                        path if path.starts_with("/address/") && path.ends_with("/balance") => {
+                            let id = &path["/address/".len()..path.len() - "/balance".len()];
+                            let network = blockchain.lock().unwrap().params().network;
+                            let address = match Address::parse_for(id, network) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing address: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let bc = blockchain.lock().unwrap();
+                            let payload = BalanceResponse {
+                                address: Address::new(network, address),
+                                balance: bc.balance(&address),
+                                height: bc.get_length(),
+                            };
+                            drop(bc);
+                            respond_json!(req, payload);
+                        }
                         _ => {
                             let content_type =
                                 "Content-Type: application/json".parse::<Header>().unwrap();
@@ -145,7 +788,9 @@ impl Server {
                     }
                 });
             }
+            info!("API server stopped");
         });
         info!("API server listening at {}", &addr);
+        Handle { running, thread }
     }
 }
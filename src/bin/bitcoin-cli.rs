@@ -0,0 +1,314 @@
+//! Command-line client of the node's API server. Keys live in a local keystore, so that
+//! transactions are created and signed here and only the signed transaction reaches the node.
+use clap::{clap_app, ArgMatches};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use bitcoin::address::Address;
+use bitcoin::config::Config;
+use bitcoin::crypto::hash::{H160, H256};
+use bitcoin::crypto::mnemonic::Mnemonic;
+use bitcoin::encoding::{Decode, Encode};
+use bitcoin::params::{ChainParams, Network};
+use bitcoin::transaction::Transaction;
+use bitcoin::wallet::{HdWallet, Keystore};
+
+fn main() {
+    let matches = clap_app!(("bitcoin-cli") =>
+     (version: "0.1")
+     (about: "Command-line client of the Bitcoin node")
+     (@setting SubcommandRequiredElseHelp)
+     (@arg network: --network [NETWORK] possible_value[mainnet testnet regtest] "Sets the chain the node runs on")
+     (@arg regtest: --regtest "Shorthand for --network regtest")
+     (@arg rpc: --rpc [ADDR] "Sets the address of the node's API server (default port depends on the network)")
+     (@arg data_dir: --("data-dir") [DIR] "Sets the directory the keystore is kept in")
+     (@arg keystore: --keystore [FILE] "Sets the keystore file (default: keystore.json in the network's data directory)")
+     (@arg passphrase: --passphrase +global [PASS] "Sets the passphrase protecting the seed, asked for when needed otherwise")
+     (@subcommand getchaininfo => (about: "Shows the height, tip and difficulty of the chain"))
+     (@subcommand getchaintips => (about: "Lists the tips of the longest chain and of the branches off it"))
+     (@subcommand getblock => (about: "Shows a block")
+      (@arg HASH: +required "Hash of the block"))
+     (@subcommand gettransaction => (about: "Shows a confirmed or pending transaction")
+      (@arg TXID: +required "Hash of the signed transaction"))
+     (@subcommand getmempool => (about: "Lists the pending transactions"))
+     (@subcommand getbalance => (about: "Shows the balance of an address, or of the whole keystore")
+      (@arg ADDRESS: "Address to query"))
+     (@subcommand createwallet => (about: "Creates a keystore with a fresh mnemonic, protected by --passphrase if given"))
+     (@subcommand importmnemonic => (about: "Creates a keystore from an existing mnemonic, protected by --passphrase if given")
+      (@arg PHRASE: +required "Mnemonic phrase, quoted"))
+     (@subcommand exportmnemonic => (about: "Prints the mnemonic of the keystore"))
+     (@subcommand getnewaddress => (about: "Hands out a fresh address of the keystore")
+      (@arg change: --change "Uses the change chain"))
+     (@subcommand listaddresses => (about: "Lists the addresses handed out by the keystore"))
+     (@subcommand createtransaction => (about: "Creates and signs a payment, printing it as hex")
+      (@arg TO: +required "Address to pay")
+      (@arg AMOUNT: +required "Amount to pay"))
+     (@subcommand sendrawtransaction => (about: "Submits a signed transaction to the node")
+      (@arg HEX: +required "Transaction as printed by createtransaction"))
+     (@subcommand send => (about: "Creates, signs and submits a payment")
+      (@arg TO: +required "Address to pay")
+      (@arg AMOUNT: +required "Amount to pay"))
+     (@subcommand miner => (about: "Controls the miner")
+      (@setting SubcommandRequiredElseHelp)
+      (@subcommand start => (about: "Starts mining")
+       (@arg lambda: --lambda +required [INT] "Sets the pause between nonces, in microseconds")
+       (@arg blocks: --blocks [INT] "Stops after mining this many blocks")
+       (@arg duration: --duration [SECS] "Stops after this many seconds"))
+      (@subcommand stop => (about: "Stops mining"))
+      (@subcommand pause => (about: "Pauses mining"))
+      (@subcommand resume => (about: "Resumes mining"))
+      (@subcommand status => (about: "Shows the state of the miner")))
+     (@subcommand generator => (about: "Controls the transaction generator")
+      (@setting SubcommandRequiredElseHelp)
+      (@subcommand start => (about: "Starts generating transactions")
+       (@arg lambda: --lambda +required [INT] "Sets the pause between transactions, in microseconds"))
+      (@subcommand stop => (about: "Stops generating transactions")))
+     (@subcommand generate => (about: "Mines blocks at once, on regtest")
+      (@arg N: +required "Number of blocks")
+      (@arg ADDRESS: "Address the coinbases pay to (default: a fresh keystore address)"))
+     (@subcommand stop => (about: "Stops the node"))
+    )
+    .get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// The local keystore and the wallet it restores.
+struct Wallet {
+    path: PathBuf,
+    keystore: Keystore,
+    hd: HdWallet,
+}
+
+impl Wallet {
+    fn create(path: &Path, network: Network, mnemonic: Mnemonic, passphrase: Option<&str>) -> Result<Self, String> {
+        if path.exists() {
+            return Err(format!("keystore {} already exists", path.display()));
+        }
+        let coin_type = ChainParams::for_network(network).coin_type;
+        let passphrase = passphrase.unwrap_or("");
+        let hd = HdWallet::from_mnemonic(mnemonic, passphrase, coin_type);
+        let wallet = Wallet {
+            path: path.to_path_buf(),
+            keystore: Keystore::new(network, &hd, !passphrase.is_empty()),
+            hd,
+        };
+        wallet.save()?;
+        Ok(wallet)
+    }
+
+    /// Open the keystore, asking for its passphrase unless `passphrase` is given.
+    fn open(path: &Path, network: Network, passphrase: Option<&str>) -> Result<Self, String> {
+        let keystore = Keystore::load(path, network)?;
+        let passphrase = match (keystore.protected, passphrase) {
+            (false, _) => String::new(),
+            (true, Some(passphrase)) => passphrase.to_string(),
+            (true, None) => prompt("Passphrase: ")?,
+        };
+        Ok(Wallet { path: path.to_path_buf(), hd: keystore.wallet(&passphrase)?, keystore })
+    }
+
+    /// Write the keystore with the addresses handed out so far.
+    fn save(&self) -> Result<(), String> {
+        Keystore::new(self.keystore.network, &self.hd, self.keystore.protected).save(&self.path)
+    }
+
+    fn address(&self, hash: H160) -> Address {
+        Address::new(self.keystore.network, hash)
+    }
+}
+
+/// A line typed on the terminal after `message`.
+fn prompt(message: &str) -> Result<String, String> {
+    eprint!("{}", message);
+    std::io::stderr().flush().map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).map_err(|e| format!("error reading input: {}", e))?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Client of the node's API server, speaking just enough HTTP/1.1 for it.
+struct Client {
+    addr: String,
+}
+
+impl Client {
+    fn get(&self, path: &str) -> Result<Value, String> {
+        self.call("GET", path, "")
+    }
+
+    fn post(&self, path: &str, body: &str) -> Result<Value, String> {
+        self.call("POST", path, body)
+    }
+
+    /// The JSON reply to a request, or the message of an unsuccessful one.
+    fn call(&self, method: &str, path: &str, body: &str) -> Result<Value, String> {
+        let mut stream = TcpStream::connect(&self.addr)
+            .map_err(|e| format!("cannot connect to the node at {}: {}", self.addr, e))?;
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method, path, self.addr, body.len(), body
+        ).map_err(|e| format!("error sending request: {}", e))?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| format!("error reading reply: {}", e))?;
+        let body = response.split_once("\r\n\r\n")
+            .map(|(_, body)| body)
+            .ok_or_else(|| "malformed reply".to_string())?;
+        let reply: Value = serde_json::from_str(body).map_err(|e| format!("error parsing reply: {}", e))?;
+        if reply.get("success") == Some(&Value::Bool(false)) {
+            return Err(reply["message"].as_str().unwrap_or("request failed").to_string());
+        }
+        Ok(reply)
+    }
+}
+
+/// Print a reply: the message of a plain result, else the whole JSON.
+fn print(reply: &Value) {
+    match (reply.get("success"), reply.get("message").and_then(Value::as_str)) {
+        (Some(Value::Bool(true)), Some(message)) => println!("{}", message),
+        _ => println!("{}", serde_json::to_string_pretty(reply).unwrap()),
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let network = if matches.is_present("regtest") {
+        Network::Regtest
+    } else {
+        matches.value_of("network").map(str::parse).transpose()?.unwrap_or(Network::Mainnet)
+    };
+    let params = ChainParams::for_network(network);
+    let client = Client {
+        addr: matches.value_of("rpc").map(str::to_string)
+            .unwrap_or_else(|| format!("127.0.0.1:{}", params.default_api_port)),
+    };
+    let keystore = match matches.value_of("keystore") {
+        Some(path) => PathBuf::from(path),
+        None => matches.value_of("data_dir").map(PathBuf::from)
+            .unwrap_or_else(|| Config::default().data_dir)
+            .join(network.to_string())
+            .join("keystore.json"),
+    };
+    let passphrase = matches.value_of("passphrase");
+
+    match matches.subcommand() {
+        ("getchaininfo", _) => print(&client.get("/chain/info")?),
+        ("getchaintips", _) => print(&client.get("/chain/tips")?),
+        ("getblock", Some(args)) => print(&client.get(&format!("/block/{}", args.value_of("HASH").unwrap()))?),
+        ("gettransaction", Some(args)) => print(&client.get(&format!("/tx/{}", args.value_of("TXID").unwrap()))?),
+        ("getmempool", _) => print(&client.get("/mempool")?),
+        ("getbalance", Some(args)) => match args.value_of("ADDRESS") {
+            Some(address) => print(&client.get(&format!("/address/{}/balance", address))?),
+            None => {
+                let wallet = Wallet::open(&keystore, network, passphrase)?;
+                let mut total = 0;
+                for hash in wallet.hd.addresses() {
+                    let reply = client.get(&format!("/address/{}/balance", wallet.address(hash)))?;
+                    total += reply["balance"].as_u64().unwrap_or(0);
+                }
+                println!("{}", total);
+            }
+        },
+        ("createwallet", _) => {
+            let mnemonic = Mnemonic::generate(24)?;
+            let wallet = Wallet::create(&keystore, network, mnemonic, passphrase)?;
+            println!("Created keystore {}; write the mnemonic down:", keystore.display());
+            println!("{}", wallet.hd.mnemonic().phrase());
+        }
+        ("importmnemonic", Some(args)) => {
+            let mnemonic = Mnemonic::parse(args.value_of("PHRASE").unwrap())?;
+            Wallet::create(&keystore, network, mnemonic, passphrase)?;
+            println!("Created keystore {}", keystore.display());
+        }
+        ("exportmnemonic", _) => println!("{}", Keystore::load(&keystore, network)?.mnemonic),
+        ("getnewaddress", Some(args)) => {
+            let mut wallet = Wallet::open(&keystore, network, passphrase)?;
+            let hash = if args.is_present("change") {
+                wallet.hd.change_address()
+            } else {
+                wallet.hd.receive_address()
+            };
+            wallet.save()?;
+            println!("{}", wallet.address(hash));
+        }
+        ("listaddresses", _) => {
+            let wallet = Wallet::open(&keystore, network, passphrase)?;
+            for hash in wallet.hd.addresses() {
+                println!("{}", wallet.address(hash));
+            }
+        }
+        ("createtransaction", Some(args)) => {
+            let tx = create_transaction(&client, &keystore, network, passphrase, args)?;
+            println!("{}", hex::encode(tx));
+        }
+        ("sendrawtransaction", Some(args)) => print(&client.post("/tx/submit", args.value_of("HEX").unwrap())?),
+        ("send", Some(args)) => {
+            let tx = create_transaction(&client, &keystore, network, passphrase, args)?;
+            print(&client.post("/tx/submit", &hex::encode(tx))?);
+        }
+        ("miner", Some(args)) => match args.subcommand() {
+            ("start", Some(args)) => {
+                let mut path = format!("/miner/start?lambda={}", args.value_of("lambda").unwrap());
+                for name in &["blocks", "duration"] {
+                    if let Some(value) = args.value_of(name) {
+                        path.push_str(&format!("&{}={}", name, value));
+                    }
+                }
+                print(&client.get(&path)?);
+            }
+            (command, _) => print(&client.get(&format!("/miner/{}", command))?),
+        },
+        ("generator", Some(args)) => match args.subcommand() {
+            ("start", Some(args)) => print(&client.get(&format!("/trans/start?lambda={}", args.value_of("lambda").unwrap()))?),
+            _ => print(&client.get("/trans/end")?),
+        },
+        ("generate", Some(args)) => {
+            let address = match args.value_of("ADDRESS") {
+                Some(address) => address.to_string(),
+                None => {
+                    let mut wallet = Wallet::open(&keystore, network, passphrase)?;
+                    let hash = wallet.hd.receive_address();
+                    wallet.save()?;
+                    wallet.address(hash).to_string()
+                }
+            };
+            print(&client.get(&format!("/regtest/generate?n={}&address={}", args.value_of("N").unwrap(), address))?);
+        }
+        ("stop", _) => print(&client.get("/node/stop")?),
+        (command, _) => return Err(format!("unknown command {}", command)),
+    }
+    Ok(())
+}
+
+/// Sign a payment of `AMOUNT` to `TO` out of the keystore's unspent transactions, returning
+/// its consensus encoding.
+fn create_transaction(client: &Client, keystore: &Path, network: Network, passphrase: Option<&str>, args: &ArgMatches) -> Result<Vec<u8>, String> {
+    let to = Address::parse_for(args.value_of("TO").unwrap(), network)?;
+    let amount = args.value_of("AMOUNT").unwrap().parse::<u8>()
+        .map_err(|e| format!("error parsing amount: {}", e))?;
+    let mut wallet = Wallet::open(keystore, network, passphrase)?;
+
+    // a transaction paying several of our addresses is listed under each
+    let mut seen: HashSet<H256> = HashSet::new();
+    let mut unspent = Vec::new();
+    for hash in wallet.hd.addresses() {
+        let reply = client.get(&format!("/address/{}/unspent", wallet.address(hash)))?;
+        for entry in reply.as_array().cloned().unwrap_or_default() {
+            let bytes = hex::decode(entry["hex"].as_str().unwrap_or("")).map_err(|e| e.to_string())?;
+            let tx = Transaction::decode(&bytes)?;
+            if seen.insert(tx.id) {
+                unspent.push(tx);
+            }
+        }
+    }
+    let tx = wallet.hd.create_transaction(&unspent, to, amount)?;
+    // the change address is handed out even if the transaction is never sent
+    wallet.save()?;
+    Ok(tx.encode())
+}
@@ -1,11 +1,20 @@
-use crate::crypto::{merkle::MerkleTree, hash::{H256, Hashable}};
+use crate::crypto::{merkle::MerkleTree, hash::{tagged_hash, tags, H256, Hashable}};
 use crate::signedtrans::{SignedTrans, generate_random_signedtrans};
+use crate::encoding::{Decode, Encode, Reader};
 use serde::{Serialize, Deserialize};
 use rand::Rng;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Top bits of every header version. The 29 bits below are signals, one per deployment of new
+/// validation rules, that miners set to show they are ready to enforce it.
+pub const VERSION_BITS_TOP: u32 = 0x2000_0000;
+/// Mask of the top bits
+pub const VERSION_BITS_MASK: u32 = 0xe000_0000;
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Header {
+    version: u32,
+    height: u32,
     pub parent: H256,
     nonce: u32,
     pub difficulty: H256,
@@ -19,19 +28,84 @@ pub struct Block {
     pub content: Vec<SignedTrans>
 }
 
+/// Encoded as the version, height, parent, nonce, difficulty, timestamp, then the merkle root
+impl Encode for Header {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.version.encode_to(out);
+        self.height.encode_to(out);
+        self.parent.encode_to(out);
+        self.nonce.encode_to(out);
+        self.difficulty.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.merkle_root.encode_to(out);
+    }
+}
+
+impl Decode for Header {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Header {
+            version: u32::decode_from(reader)?,
+            height: u32::decode_from(reader)?,
+            parent: H256::decode_from(reader)?,
+            nonce: u32::decode_from(reader)?,
+            difficulty: H256::decode_from(reader)?,
+            timestamp: u128::decode_from(reader)?,
+            merkle_root: H256::decode_from(reader)?,
+        })
+    }
+}
+
 impl Hashable for Header {
     fn hash(&self) -> H256 {
-        let serialized = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &serialized).into()
+        tagged_hash(tags::HEADER, &self.encode())
     }
 }
 
 impl Header {
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    /// Whether the version uses version bits and signals `bit`
+    pub fn signals(&self, bit: u8) -> bool {
+        self.version & VERSION_BITS_MASK == VERSION_BITS_TOP && self.version & (1 << bit) != 0
+    }
+
+    pub fn get_height(&self) -> u32 {
+        self.height
+    }
+
     pub fn get_create_time(&self) -> u128 {
         self.timestamp
     }
+
+    pub fn get_merkle_root(&self) -> H256 {
+        self.merkle_root
+    }
+
+    pub fn set_nonce(&mut self, nonce: u32) {
+        self.nonce = nonce;
+    }
+}
+
+/// Encoded as the header, then the transactions
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.content.encode_to(out);
+    }
 }
 
+impl Decode for Block {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let header = Header::decode_from(reader)?;
+        let content = Vec::<SignedTrans>::decode_from(reader)?;
+        Ok(Block { header, content })
+    }
+}
+
+/// A block is identified by the hash of its header, which commits to the content through the
+/// merkle root.
 impl Hashable for Block {
     fn hash(&self) -> H256 {
         self.header.hash()
@@ -39,9 +113,12 @@ impl Hashable for Block {
 }
 
 impl Block {
-    pub fn new(parent: H256, nonce:u32, difficulty:H256, timestamp:u128,
-               merkle_root:H256, content:Vec<SignedTrans>) -> Block {
-        Block{ header: Header{ parent, nonce, difficulty, timestamp,merkle_root}, content}
+    pub fn builder() -> BlockBuilder {
+        BlockBuilder::default()
+    }
+
+    pub fn get_nonce(&self) -> u32 {
+        self.header.nonce
     }
 
     pub fn get_difficulty(&self) -> H256 {
@@ -49,6 +126,80 @@ impl Block {
     }
 }
 
+/// Builds a block field by field. The version defaults to `VERSION_BITS_TOP` without signals and
+/// the merkle root is computed from the transactions unless given.
+#[derive(Debug, Clone)]
+pub struct BlockBuilder {
+    header: Header,
+    merkle_root: Option<H256>,
+    content: Vec<SignedTrans>,
+}
+
+impl Default for BlockBuilder {
+    fn default() -> Self {
+        BlockBuilder {
+            header: Header { version: VERSION_BITS_TOP, ..Default::default() },
+            merkle_root: None,
+            content: Vec::new(),
+        }
+    }
+}
+
+impl BlockBuilder {
+    pub fn version(mut self, version: u32) -> Self {
+        self.header.version = version;
+        self
+    }
+
+    /// Set the signal of a deployment in the version
+    pub fn signal(mut self, bit: u8) -> Self {
+        self.header.version |= 1 << bit;
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> Self {
+        self.header.height = height;
+        self
+    }
+
+    pub fn parent(mut self, parent: H256) -> Self {
+        self.header.parent = parent;
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u32) -> Self {
+        self.header.nonce = nonce;
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: H256) -> Self {
+        self.header.difficulty = difficulty;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u128) -> Self {
+        self.header.timestamp = timestamp;
+        self
+    }
+
+    /// Use a merkle root already computed for the transactions
+    pub fn merkle_root(mut self, merkle_root: H256) -> Self {
+        self.merkle_root = Some(merkle_root);
+        self
+    }
+
+    pub fn transactions(mut self, content: Vec<SignedTrans>) -> Self {
+        self.content = content;
+        self
+    }
+
+    pub fn build(self) -> Block {
+        let merkle_root = self.merkle_root.unwrap_or_else(|| MerkleTree::new(&self.content).root());
+        let header = Header { merkle_root, ..self.header };
+        Block { header, content: self.content }
+    }
+}
+
 pub fn generate_random_block(parent: &H256) -> Block {
     let parent_array: [u8; 32] = parent.into();
 
@@ -65,43 +216,13 @@ pub fn generate_random_block(parent: &H256) -> Block {
         generate_random_signedtrans().into()
     ];
 
-    let merkle_tree = MerkleTree::new(&trans);
-    let root = merkle_tree.root();
-
-    let blk = Block::new(
-        H256::from(parent_array),
-        rand::thread_rng().gen::<u32>(),
-        H256::from(result),
-        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis(),
-        root,
-        trans,
-    );
-
-    //println!("{:?}", blk);
-    blk
-}
-
-pub fn generate_genesis_block(parent: &H256) -> Block {
-    let parent_array: [u8; 32] = parent.into();
-
-    // init random difficulty
-    let mut result = [0u8; 32];
-    // let sr = ring::rand::SystemRandom::new();
-    // sr.fill(&mut result).unwrap(); // random difficulty
-    result[1] = 16;
-
-    let data:Vec<SignedTrans> = Vec::new();
-    let merkle_tree = MerkleTree::new(&data);
-    let root = merkle_tree.root();
-
-    let blk = Block::new(
-        H256::from(parent_array),
-        0,
-        H256::from(result),
-        0,
-        root,
-        Vec::new(),
-    );
+    let blk = Block::builder()
+        .parent(H256::from(parent_array))
+        .nonce(rand::thread_rng().gen::<u32>())
+        .difficulty(H256::from(result))
+        .timestamp(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis())
+        .transactions(trans)
+        .build();
 
     //println!("{:?}", blk);
     blk
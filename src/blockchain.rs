@@ -1,43 +1,143 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
-use std::time::SystemTime;
-use crate::block::Block;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::net::IpAddr;
+use std::sync::Arc;
+use crate::block::{Block, Header, VERSION_BITS_MASK, VERSION_BITS_TOP};
+use crate::crypto::batch::{SignatureCheck, SignatureVerifier};
 use crate::crypto::hash::{H160, H256, Hashable};
-use crate::block::generate_genesis_block;
+use crate::crypto::merkle::{self, MerkleTree};
+use crate::encoding::{Decode, Encode};
+use serde::{Serialize, Deserialize};
+use crate::params::{Activation, ChainParams, COINBASE_SUBSIDY};
 use crate::signedtrans::SignedTrans;
-use crate::transaction::Transaction;
+use crate::transaction::{balance, Output, Transaction};
+use crate::spv::SpvWallet;
+use crate::filter::BlockFilter;
+use crate::clock::{median_time, Clock, NetworkTime, MEDIAN_TIME_SPAN};
 use crate::state::State;
+use crate::events::{Event, EventBus};
+
+/// Proof that a transaction is included in a block, which can be checked against the block
+/// header alone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TxProof {
+    pub header: Header,
+    pub txid: H256,
+    pub index: usize,
+    pub leaf_count: usize,
+    pub proof: Vec<H256>,
+}
+
+impl TxProof {
+    /// Check the proof path against the merkle root in the header.
+    pub fn verify(&self) -> bool {
+        merkle::verify(&self.header.get_merkle_root(), &self.txid, &self.proof, self.index, self.leaf_count)
+    }
+}
+
+/// The last block of a branch, as listed by `Blockchain::tips`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainTip {
+    pub hash: H256,
+    pub height: u32,
+    /// Expected number of hashes to mine the chain up to the tip, genesis included
+    pub work: f64,
+    /// Last block the branch shares with the longest chain; the tip itself if on it
+    pub fork_point: H256,
+    /// Blocks after the fork point
+    pub branch_length: u32,
+    /// Whether this is the tip of the longest chain
+    pub active: bool,
+}
+
+/// Expected number of hashes to find a block meeting `target`, 2^256 / (target + 1).
+pub fn block_work(target: &H256) -> f64 {
+    let target = target.as_ref().iter().fold(0f64, |acc, byte| acc * 256.0 + *byte as f64);
+    2f64.powi(256) / (target + 1.0)
+}
 
 #[derive(Debug, Clone)]
 pub struct Blockchain {
     pub blockchain: HashMap<H256,Block>, //blocks in the blockchain
+    longest: Vec<H256>, // hashes of the blocks in the blockchain, by height
     pub blocks: HashMap<H256,(Block,u32)>, //all blocks in the network, u32 refers to the height of that block
     height: u32,
     tip: H256,
     block_num:u128,
     pub current_state: State,
-    pub address_list: Vec<H160>
+    pub address_list: Vec<H160>,
+    params: ChainParams,
+    spv: Option<SpvWallet>, // set on light clients, which only store headers
+    filters: HashMap<H256, BlockFilter>, // compact filters of the blocks, full nodes only
+    time: NetworkTime,
+    events: Arc<EventBus>, // receives the blocks connected to and disconnected from the longest chain
+    outputs: HashMap<H256, Vec<(H256, Vec<Output>)>>, // transaction id and the blocks of any branch confirming it, with its outputs
+    transactions: HashMap<H256, Vec<(H256, usize)>>, // transaction hash and the blocks of any branch holding it, with its position
+    deployment_states: HashMap<(&'static str, H256), bool>, // whether a signalled deployment is active after a completed window, by the window's last block
 }
 
 impl Blockchain {
-    /// Create a new blockchain, only containing the genesis block
+    /// Create a new mainnet blockchain, only containing the genesis block
     pub fn new() -> Self {
+        Self::with_params(ChainParams::mainnet())
+    }
+
+    /// Create a new blockchain of the given network, only containing its genesis block
+    pub fn with_params(params: ChainParams) -> Self {
         let mut blocks = HashMap::new();
         let mut blockchain = HashMap::new();
 
-        let genesis = generate_genesis_block(&H256::from([0u8; 32]));
+        let genesis = params.genesis_block();
 
         let hashvalue = genesis.hash();
         blocks.insert(hashvalue,(genesis.clone(),0));
         blockchain.insert(hashvalue,genesis.clone());
+        let mut filters = HashMap::new();
+        filters.insert(hashvalue, BlockFilter::new(&genesis));
         Blockchain{
             blockchain,
+            longest: vec![hashvalue],
             blocks,
             height: 0,
             tip: hashvalue,
             block_num: 0,
             current_state: State::new(),
             address_list: Vec::new(),
+            params,
+            spv: None,
+            filters,
+            time: NetworkTime::default(),
+            events: Arc::default(),
+            outputs: HashMap::new(),
+            transactions: HashMap::new(),
+            deployment_states: HashMap::new(),
+        }
+    }
+
+    /// Create a light client chain: headers only, plus the transactions touching `addresses`
+    pub fn light(params: ChainParams, addresses: &[H160]) -> Self {
+        let mut chain = Self::with_params(params);
+        chain.spv = Some(SpvWallet::new(addresses));
+        chain.filters.clear();
+        chain
+    }
+
+    pub fn is_light(&self) -> bool {
+        self.spv.is_some()
+    }
+
+    /// The light client wallet, None on full nodes
+    pub fn spv_wallet(&self) -> Option<&SpvWallet> {
+        self.spv.as_ref()
+    }
+
+    /// Check a transaction sent with its merkle proof by a full peer and keep it in the light client wallet
+    pub fn add_proven_transaction(&mut self, tx: &SignedTrans, proof: &TxProof) -> Result<(), String> {
+        match self.spv.as_mut() {
+            Some(wallet) => wallet.add_proven(&self.blocks, tx, proof),
+            None => Err("not a light client".to_string()),
         }
     }
 
@@ -53,6 +153,8 @@ impl Blockchain {
             self.height = self.height+1;
             nheight = self.height;
             self.blockchain.insert(self.tip, block.clone());
+            self.longest.push(self.tip);
+            self.events.publish(Event::BlockConnected { hash: self.tip, height: nheight });
         //after insert this block, another branch becomes the longest chain
         } else if self.height < self.blocks.get(&parent).unwrap().1 +1 {
             nheight = self.blocks.get(&parent).unwrap().1 +1;
@@ -67,29 +169,427 @@ impl Blockchain {
                 new_chain.push(current_block.hash()); 
             }
             //remove the blocks from blockchain
+            let old_tip = self.tip;
+            let mut disconnected = 0;
             while self.tip != self.blockchain.get(&latest_parent).unwrap().hash(){ 
                 self.blockchain.remove_entry(&self.tip);
+                self.events.publish(Event::BlockDisconnected { hash: self.tip, height: self.blocks[&self.tip].1 });
+                disconnected += 1;
                 self.tip = self.blocks.get(&self.tip).unwrap().0.header.parent; 
             }
+            self.longest.truncate(self.blocks[&self.tip].1 as usize + 1);
             //insert the blocks in new_chain into blockchain
             let mut temp: Block;
             for i in new_chain.iter().rev(){ 
                 temp = self.blocks.get(&i).unwrap().0.clone();
                 self.blockchain.insert(*i, temp);
+                self.longest.push(*i);
+                self.events.publish(Event::BlockConnected { hash: *i, height: self.blocks[i].1 });
             }
             self.tip = newblock.hash();
             self.blockchain.insert(self.tip, block.clone());
+            self.longest.push(self.tip);
+            self.events.publish(Event::BlockConnected { hash: self.tip, height: nheight });
+            if disconnected > 0 {
+                self.events.publish(Event::Reorg {
+                    old_tip,
+                    new_tip: self.tip,
+                    fork_point: *latest_parent,
+                    disconnected,
+                    connected: new_chain.len() + 1,
+                });
+            }
         } else {
             //the blockchain doestn't change, only insert new block into blocks
             nheight = self.blocks.get(&parent).unwrap().1 + 1;
         }
         self.blocks.insert(newblock.hash(), (block.clone(), nheight));
+        for (index, tx) in block.content.iter().enumerate() {
+            self.outputs.entry(tx.transaction.id).or_default().push((newblock.hash(), tx.transaction.outputs.clone()));
+            self.transactions.entry(tx.hash()).or_default().push((newblock.hash(), index));
+        }
+        self.update_deployment_states(&newblock.hash(), nheight);
         self.block_num += 1;
+        if !self.is_light() {
+            self.filters.insert(newblock.hash(), BlockFilter::new(block));
+        }
+
+        let now = self.time.local();
+        println!("{:?} insert {:?} at {:?}, bc height:{:?}", now, block.hash(), nheight, self.height);
+
+        now.saturating_sub(block.header.get_create_time())
+    }
+
+    /// Compact filter of a known block
+    pub fn filter(&self, hash: &H256) -> Option<&BlockFilter> {
+        self.filters.get(hash)
+    }
+
+    /// Insert a header without its transactions, as light clients do
+    pub fn insert_header(&mut self, header: &Header) -> u128 {
+        self.insert(&Block { header: header.clone(), content: Vec::new() })
+    }
+
+    /// Hashes of the longest chain a peer can use to find where our chains diverge: the latest
+    /// ten blocks, then exponentially further apart, always ending with the genesis
+    pub fn locator(&self) -> Vec<H256> {
+        let chain = &self.longest;
+        let mut locator = Vec::new();
+        let mut index = chain.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(chain[index]);
+            if index == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            index = index.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Up to `max` headers of the longest chain following the first locator hash on it
+    pub fn headers_after(&self, locator: &[H256], max: usize) -> Vec<Header> {
+        let start = locator.iter()
+            .filter(|hash| self.blockchain.contains_key(hash))
+            .find_map(|hash| self.blocks.get(hash).map(|(_, height)| *height as usize))
+            .unwrap_or(0);
+        self.longest[start + 1..].iter()
+            .take(max)
+            .map(|hash| self.blockchain[hash].header.clone())
+            .collect()
+    }
+
+    /// Transactions of the longest chain paying to `addresses` or spending from such
+    /// transactions, in chain order, with their inclusion proofs
+    pub fn address_transactions(&self, addresses: &[H160]) -> Vec<(SignedTrans, TxProof)> {
+        let mut found = Vec::new();
+        let mut ours: Vec<H256> = Vec::new();
+        for hash in self.longest.iter() {
+            for tx in self.blockchain[hash].content.iter() {
+                let pays = tx.transaction.outputs.iter().any(|out| addresses.contains(&out.address));
+                let spends = tx.transaction.inputs.iter().any(|input| ours.contains(&input.previous_hash));
+                if pays || spends {
+                    ours.push(tx.transaction.id);
+                    found.push((tx.clone(), self.tx_proof(&tx.hash()).unwrap()));
+                }
+            }
+        }
+        found
+    }
+
+    /// Balance of `address` on the longest chain; light clients only know their own transactions
+    pub fn balance(&self, address: &H160) -> u64 {
+        if let Some(wallet) = self.spv.as_ref() {
+            return wallet.balance(address, |hash| self.blockchain.contains_key(hash));
+        }
+        let txs: Vec<&Transaction> = self.longest.iter()
+            .flat_map(|hash| self.blockchain[hash].content.iter().map(|tx| &tx.transaction))
+            .collect();
+        balance(&txs, address)
+    }
+
+    /// Transactions of the longest chain with an output paying `address` that neither the
+    /// longest chain nor the unconfirmed transactions spend, in chain order
+    pub fn unspent(&self, address: &H160) -> Vec<Transaction> {
+        let txs: Vec<&Transaction> = self.longest.iter()
+            .flat_map(|hash| self.blockchain[hash].content.iter().map(|tx| &tx.transaction))
+            .collect();
+        let spent: HashSet<H256> = txs.iter().cloned()
+            .chain(self.current_state.sig.values().map(|tx| &tx.transaction))
+            .flat_map(|tx| tx.inputs.iter().map(|input| input.previous_hash))
+            .collect();
+        txs.into_iter()
+            .filter(|tx| !spent.contains(&tx.id) && tx.outputs.iter().any(|out| out.address == *address))
+            .cloned()
+            .collect()
+    }
+
+    /// The transaction with hash `txid` on the longest chain, with the hash and height of its block
+    pub fn find_transaction(&self, txid: &H256) -> Option<(SignedTrans, H256, u32)> {
+        let (hash, index) = self.locate_transaction(txid)?;
+        let (block, height) = &self.blocks[&hash];
+        Some((block.content[index].clone(), hash, *height))
+    }
+
+    /// The block of the longest chain holding the transaction with hash `txid`, and its position
+    fn locate_transaction(&self, txid: &H256) -> Option<(H256, usize)> {
+        self.transactions.get(txid)?.iter()
+            .find(|(hash, _)| self.blockchain.contains_key(hash))
+            .cloned()
+    }
+
+    /// Write every known block except the genesis to `path`, parents before children.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut blocks: Vec<&(Block, u32)> = self.blocks.values().filter(|(_, h)| *h > 0).collect();
+        blocks.sort_by_key(|(_, h)| *h);
+        let blocks: Vec<Block> = blocks.into_iter().map(|(b, _)| b.clone()).collect();
+        std::fs::write(path, blocks.encode())
+    }
+
+    /// Insert the blocks written by `save` into a new blockchain.
+    pub fn load(&mut self, path: &Path) -> std::io::Result<()> {
+        let encoded = std::fs::read(path)?;
+        let blocks = Vec::<Block>::decode(&encoded)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for block in blocks.iter() {
+            if !self.blocks.contains_key(&block.header.parent) {
+                return Err(Error::new(ErrorKind::InvalidData, "block stored before its parent"));
+            }
+            self.insert(block);
+        }
+        Ok(())
+    }
+
+    /// Build an inclusion proof for the transaction with hash `txid` on the longest chain
+    pub fn tx_proof(&self, txid: &H256) -> Option<TxProof> {
+        let (hash, index) = self.locate_transaction(txid)?;
+        let block = &self.blockchain[&hash];
+        let tree = MerkleTree::new(&block.content);
+        Some(TxProof {
+            header: block.header.clone(),
+            txid: *txid,
+            index,
+            leaf_count: block.content.len(),
+            proof: tree.proof(index),
+        })
+    }
+
+    /// Publish the changes to the longest chain to `events`
+    pub fn set_events(&mut self, events: Arc<EventBus>) {
+        self.events = events;
+    }
+
+    /// Replace the clock timestamps are checked against
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.time = NetworkTime::new(clock);
+    }
+
+    /// Record the time a peer reported in its handshake
+    pub fn add_time_sample(&mut self, peer: IpAddr, peer_time: u128) {
+        self.time.add_sample(peer, peer_time);
+    }
+
+    /// Forget the time a disconnected peer reported
+    pub fn remove_time_sample(&mut self, peer: IpAddr) {
+        self.time.remove_sample(peer);
+    }
+
+    /// Local time corrected by the peers' clocks
+    pub fn adjusted_time(&self) -> u128 {
+        self.time.adjusted()
+    }
+
+    /// Median timestamp of the block `hash` and its ancestors, up to `MEDIAN_TIME_SPAN` blocks
+    pub fn median_time_past(&self, hash: &H256) -> u128 {
+        let mut timestamps = Vec::new();
+        let mut hash = *hash;
+        while let Some((block, height)) = self.blocks.get(&hash) {
+            timestamps.push(block.header.get_create_time());
+            if *height == 0 || timestamps.len() == MEDIAN_TIME_SPAN {
+                break;
+            }
+            hash = block.header.parent;
+        }
+        median_time(&timestamps)
+    }
+
+    /// Whether the deployment `name` is active for a child of the block `parent`.
+    pub fn is_active(&self, name: &str, parent: &H256) -> bool {
+        let (deployment, height) = match (self.params.deployment(name), self.blocks.get(parent)) {
+            (Some(deployment), Some((_, parent_height))) => (deployment, parent_height + 1),
+            _ => return false,
+        };
+        match deployment.activation {
+            Activation::Height(activation) => height >= activation,
+            Activation::Signal { window, .. } => {
+                // only the windows completed before `height` count
+                let complete = height / window * window;
+                if complete == 0 {
+                    return false;
+                }
+                let last = self.ancestor(parent, complete - 1);
+                self.deployment_states.get(&(deployment.name, last)).cloned().unwrap_or(false)
+            }
+        }
+    }
+
+    /// Cache the state of the signalled deployments if the block `hash` at `height` completes
+    /// a window: active if it was already, or if enough blocks of the window signalled.
+    fn update_deployment_states(&mut self, hash: &H256, height: u32) {
+        for deployment in self.params.deployments.clone() {
+            let (bit, window, threshold) = match deployment.activation {
+                Activation::Signal { bit, window, threshold } if (height + 1).is_multiple_of(window) => (bit, window, threshold),
+                _ => continue,
+            };
+            let mut signals = 0;
+            let mut current = *hash;
+            for _ in 0..window {
+                let block = &self.blocks[&current].0;
+                if block.header.signals(bit) {
+                    signals += 1;
+                }
+                current = block.header.parent;
+            }
+            // `current` is now the last block of the previous window, unless this is the first one
+            let active = (height + 1 > window
+                && self.deployment_states.get(&(deployment.name, current)).cloned().unwrap_or(false))
+                || signals >= threshold;
+            self.deployment_states.insert((deployment.name, *hash), active);
+        }
+    }
+
+    /// The ancestor at `height` of the known block `hash`, which must not be below it.
+    fn ancestor(&self, hash: &H256, height: u32) -> H256 {
+        let mut hash = *hash;
+        while self.blocks[&hash].1 > height {
+            hash = self.blocks[&hash].0.header.parent;
+        }
+        hash
+    }
+
+    /// Version of a child of the block `parent`: the version bits of every signalled
+    /// deployment that is not active yet.
+    pub fn block_version(&self, parent: &H256) -> u32 {
+        let mut version = VERSION_BITS_TOP;
+        for deployment in self.params.deployments.iter() {
+            if let Activation::Signal { bit, .. } = deployment.activation {
+                if !self.is_active(deployment.name, parent) {
+                    version |= 1 << bit;
+                }
+            }
+        }
+        version
+    }
+
+    /// Check the parts of a block a light client can check: version, height, known parent,
+    /// timestamp, unchanged difficulty and proof of work. The timestamp must be after the median
+    /// time past of the parent and at most `max_future_drift` ahead of network-adjusted time.
+    pub fn validate_header(&self, header: &Header) -> Result<(), String> {
+        if header.get_version() & VERSION_BITS_MASK != VERSION_BITS_TOP {
+            return Err(format!("unsupported block version {:#x}", header.get_version()));
+        }
+        let (parent, parent_height) = match self.blocks.get(&header.parent) {
+            Some((parent, height)) => (parent, height),
+            None => return Err(format!("unknown parent {:?}", header.parent)),
+        };
+        if header.get_height() != parent_height + 1 {
+            return Err(format!("block height {} does not follow its parent's", header.get_height()));
+        }
+        if header.get_create_time() <= self.median_time_past(&header.parent) {
+            return Err("block timestamp is not after the median time past".to_string());
+        }
+        if header.get_create_time() > self.adjusted_time() + self.params.max_future_drift {
+            return Err("block timestamp is too far in the future".to_string());
+        }
+        if header.difficulty != parent.header.difficulty {
+            return Err("difficulty does not match parent".to_string());
+        }
+        if header.hash() > header.difficulty {
+            return Err("block hash does not meet difficulty".to_string());
+        }
+        Ok(())
+    }
+
+    /// Check a block against the chain before inserting it: the header, merkle root, size, and
+    /// the rules of the active deployments.
+    pub fn validate(&self, block: &Block) -> Result<(), String> {
+        self.validate_header(&block.header)?;
+        if MerkleTree::new(&block.content).root() != block.header.get_merkle_root() {
+            return Err("merkle root does not match content".to_string());
+        }
+        // the merkle tree pairs an odd last node with itself, so repeating the last transactions
+        // keeps the root (CVE-2012-2459); a valid block never holds the same transaction twice
+        let mut hashes = HashSet::new();
+        if !block.content.iter().all(|tx| hashes.insert(tx.hash())) {
+            return Err("block contains a duplicate transaction".to_string());
+        }
+        if block.encode().len() > self.params.max_block_size {
+            return Err("block exceeds the maximum block size".to_string());
+        }
+        if self.is_active(COINBASE_SUBSIDY, &block.header.parent) {
+            let subsidy = self.params.subsidy(block.header.get_height()) as u64;
+            match block.content.first() {
+                Some(tx) if tx.transaction.is_coinbase() => {
+                    let value: u64 = tx.transaction.outputs.iter().map(|out| out.balance as u64).sum();
+                    if value > subsidy {
+                        return Err(format!("coinbase pays {}, more than the subsidy {}", value, subsidy));
+                    }
+                }
+                _ => return Err("block does not start with a coinbase".to_string()),
+            }
+            if block.content.iter().skip(1).any(|tx| tx.transaction.is_coinbase()) {
+                return Err("block has more than one coinbase".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Outputs of the transaction `id` and the height of the block confirming it, looking on
+    /// the longest chain, then among the unconfirmed transactions of the current state
+    pub fn spent_outputs(&self, id: &H256) -> Option<(Vec<Output>, u32)> {
+        self.confirmed_outputs(&self.tip, id)
+            .or_else(|| self.current_state.sig.get(id).map(|tx| (tx.transaction.outputs.clone(), self.height + 1)))
+    }
 
-        let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-        println!("{:?} insert {:?} at {:?}, bc height:{:?}", ts, block.hash(), nheight, self.height);
+    /// `spent_outputs` for the transactions of `block`, which may spend one another or the
+    /// transactions of the block's ancestors, whichever branch it is on
+    fn spent_outputs_in(&self, block: &Block, id: &H256) -> Option<(Vec<Output>, u32)> {
+        block.content.iter()
+            .find(|tx| tx.transaction.id == *id)
+            .map(|tx| (tx.transaction.outputs.clone(), block.header.get_height()))
+            .or_else(|| self.confirmed_outputs(&block.header.parent, id))
+    }
+
+    /// Outputs of the transaction `id` confirmed by `tip` or one of its ancestors, with the
+    /// height of the block confirming it
+    fn confirmed_outputs(&self, tip: &H256, id: &H256) -> Option<(Vec<Output>, u32)> {
+        self.outputs.get(id)?.iter()
+            .find(|(hash, _)| self.is_ancestor(hash, tip))
+            .map(|(hash, outputs)| (outputs.clone(), self.blocks[hash].1))
+    }
+
+    /// Whether `ancestor` is `hash` or one of its ancestors. Only the part of the branch of
+    /// `hash` off the longest chain is walked.
+    fn is_ancestor(&self, ancestor: &H256, hash: &H256) -> bool {
+        let ancestor_height = match self.blocks.get(ancestor) {
+            Some((_, height)) => *height,
+            None => return false,
+        };
+        let mut current = *hash;
+        while let Some((block, height)) = self.blocks.get(&current) {
+            if current == *ancestor {
+                return true;
+            }
+            if *height <= ancestor_height {
+                return false;
+            }
+            // the rest of the way is along the longest chain
+            if self.blockchain.contains_key(&current) {
+                return self.blockchain.contains_key(ancestor);
+            }
+            current = block.header.parent;
+        }
+        false
+    }
 
-        ts.as_millis() - block.header.get_create_time()
+    /// Every signature in the transactions of `block`, to verify in a batch ahead of
+    /// `verify_transactions`
+    pub fn signature_checks(&self, block: &Block) -> Vec<SignatureCheck> {
+        block.content.iter()
+            .flat_map(|tx| tx.signature_checks(|id| self.spent_outputs_in(block, id)))
+            .collect()
+    }
+
+    /// Check the signatures and the spending conditions of the transactions of `block`.
+    pub fn verify_transactions(&self, block: &Block, verifier: &dyn SignatureVerifier) -> Result<(), String> {
+        for tx in block.content.iter() {
+            tx.verify(|id| self.spent_outputs_in(block, id), block.header.get_height(), verifier)
+                .map_err(|e| format!("transaction {}: {}", tx.hash(), e))?;
+        }
+        Ok(())
     }
 
     pub fn update_state(&mut self, sigtrans:&SignedTrans, memp_size:usize) {
@@ -120,12 +620,13 @@ impl Blockchain {
     }
 
     pub fn print_state(&self, memp_size:usize) {
-        let mut balance:HashMap<H160, u8> = HashMap::new();
+        let mut balance:HashMap<H160, u64> = HashMap::new();
         for account in self.clone().address_list {
             balance.insert(account, 0);
         }
+        // outputs may pay addresses other than the generator's, sent from a wallet
         for (_, out) in self.clone().current_state.map {
-            *balance.get_mut(&out.address).unwrap() += out.balance;
+            *balance.entry(out.address).or_insert(0) += out.balance as u64;
         }
         println!("state:{:?} mempool size:{:?}", balance, memp_size);
     }
@@ -151,15 +652,77 @@ impl Blockchain {
         self.blockchain.contains_key(&h)
     }
 
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
 
-    /// Get all blocks' hash of the longest chain
-    #[cfg(any(test, test_utilities))]
-    pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
-        let mut block_hash: Vec<H256> = Vec::new();
-        for block in self.blockchain.iter() {
-            block_hash.push(*block.0);
+
+    /// Branches off the longest chain, one per known block without children outside it, each
+    /// from the block after the fork point to that block. Branches sharing a prefix list it
+    /// each; the highest tips come first.
+    pub fn forks(&self) -> Vec<Vec<H256>> {
+        let parents: HashSet<H256> = self.blocks.values().map(|(block, _)| block.header.parent).collect();
+        let mut tips: Vec<(&H256, u32)> = self.blocks.iter()
+            .filter(|(hash, _)| !self.blockchain.contains_key(*hash) && !parents.contains(*hash))
+            .map(|(hash, (_, height))| (hash, *height))
+            .collect();
+        tips.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        tips.into_iter()
+            .map(|(tip, _)| {
+                let mut branch = Vec::new();
+                let mut current = *tip;
+                while let Some((block, _)) = self.blocks.get(&current).filter(|_| !self.blockchain.contains_key(&current)) {
+                    branch.push(current);
+                    current = block.header.parent;
+                }
+                branch.reverse();
+                branch
+            })
+            .collect()
+    }
+
+    /// Every known block without children: the tip of the longest chain first, then the tips
+    /// of the other branches, highest first, like `getchaintips`.
+    pub fn tips(&self) -> Vec<ChainTip> {
+        let parents: HashSet<H256> = self.blocks.values().map(|(block, _)| block.header.parent).collect();
+        let mut tips: Vec<ChainTip> = self.blocks.iter()
+            .filter(|(hash, _)| !parents.contains(*hash))
+            .map(|(hash, (_, height))| {
+                let mut fork_point = *hash;
+                while !self.blockchain.contains_key(&fork_point) {
+                    fork_point = self.blocks[&fork_point].0.header.parent;
+                }
+                ChainTip {
+                    hash: *hash,
+                    height: *height,
+                    work: self.chain_work(hash),
+                    fork_point,
+                    branch_length: height - self.blocks[&fork_point].1,
+                    active: *hash == self.tip,
+                }
+            })
+            .collect();
+        tips.sort_by(|a, b| b.active.cmp(&a.active).then(b.height.cmp(&a.height)).then(a.hash.cmp(&b.hash)));
+        tips
+    }
+
+    /// Work of the chain ending at the known block `hash`
+    fn chain_work(&self, hash: &H256) -> f64 {
+        let mut work = 0.0;
+        let mut current = self.blocks.get(hash);
+        while let Some((block, height)) = current {
+            work += block_work(&block.header.difficulty);
+            if *height == 0 {
+                break;
+            }
+            current = self.blocks.get(&block.header.parent);
         }
-        block_hash
+        work
+    }
+
+    /// Get all blocks' hash of the longest chain, from the genesis to the tip
+    pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
+        self.longest.clone()
     }
 }
 
@@ -168,6 +731,17 @@ mod tests {
     use super::*;
     use crate::crypto::hash::Hashable;
     use crate::block::generate_random_block;
+    use crate::events::EventKind;
+    use crate::mempool::Mempool;
+    use crate::miner::generate_blocks;
+    use crate::params::Deployment;
+    use crate::clock::MockClock;
+    use crate::transaction::{coin_base, sign, sign_input, Input};
+    use crate::condition::{Condition, SigHashType};
+    use crate::crypto::sigcache::SignatureCache;
+    use crate::crypto::batch::DirectVerifier;
+    use crate::crypto::key_pair;
+    use ring::signature::KeyPair;
 
     #[test]
     fn insert_one() {
@@ -213,4 +787,443 @@ mod tests {
             }
         assert_eq!(result, vec![ genesis_hash, block2.hash(), block3.hash(), block5.hash()]);
       }
+
+    #[test]
+    fn tx_proof() {
+        let mut blockchain = Blockchain::new();
+        let block = generate_random_block(&blockchain.tip());
+        blockchain.insert(&block);
+        for (index, tx) in block.content.iter().enumerate() {
+            let proof = blockchain.tx_proof(&tx.hash()).unwrap();
+            assert_eq!(proof.index, index);
+            assert_eq!(proof.leaf_count, 3);
+            assert_eq!(proof.header.hash(), block.hash());
+            assert!(proof.verify());
+        }
+        let mut forged = blockchain.tx_proof(&block.content[0].hash()).unwrap();
+        forged.txid = block.content[1].hash();
+        assert!(!forged.verify());
+        assert!(blockchain.tx_proof(&H256::default()).is_none());
+        // only the longest chain counts
+        let stale = generate_random_block(&block.header.parent);
+        blockchain.insert(&stale);
+        assert!(blockchain.tx_proof(&stale.content[0].hash()).is_none());
+        assert!(blockchain.find_transaction(&stale.content[0].hash()).is_none());
+        assert_eq!(blockchain.find_transaction(&block.content[2].hash()).unwrap().1, block.hash());
+    }
+
+    #[test]
+    fn headers_sync() {
+        let address: H160 = [1u8; 20].into();
+        let mut full = Blockchain::with_params(ChainParams::regtest());
+        generate_blocks(&mut full, &mut Mempool::new(), 30, &address);
+        let mut light = Blockchain::light(ChainParams::regtest(), &[address]);
+
+        let locator = light.locator();
+        assert_eq!(locator, vec![light.tip()]);
+        for header in full.headers_after(&locator, 20) {
+            light.validate_header(&header).unwrap();
+            light.insert_header(&header);
+        }
+        assert_eq!(light.get_length(), 20);
+        let locator = light.locator();
+        assert_eq!(locator.first(), Some(&light.tip()));
+        assert_eq!(locator.last(), full.all_blocks_in_longest_chain().first());
+        for header in full.headers_after(&locator, 20) {
+            light.insert_header(&header);
+        }
+        assert_eq!(light.tip(), full.tip());
+
+        for (tx, proof) in full.address_transactions(&[address]) {
+            light.add_proven_transaction(&tx, &proof).unwrap();
+        }
+        assert_eq!(light.balance(&address), full.balance(&address));
+        assert_eq!(full.balance(&address), 300);
+
+        // locator hashes off the longest chain are skipped
+        let chain = full.all_blocks_in_longest_chain();
+        let stale = generate_random_block(&chain[0]);
+        full.insert(&stale);
+        let after: Vec<H256> = full.headers_after(&[stale.hash(), chain[5]], 3).iter().map(|h| h.hash()).collect();
+        assert_eq!(after, chain[6..9].to_vec());
+
+        // and the longest chain follows reorgs
+        let mut bc = Blockchain::new();
+        let genesis = bc.tip();
+        let main = generate_random_block(&genesis);
+        let fork = generate_random_block(&genesis);
+        let fork2 = generate_random_block(&fork.hash());
+        for block in [&main, &fork, &fork2] {
+            bc.insert(block);
+        }
+        assert_eq!(bc.all_blocks_in_longest_chain(), vec![genesis, fork.hash(), fork2.hash()]);
+        assert_eq!(bc.locator(), vec![fork2.hash(), fork.hash(), genesis]);
+    }
+
+    #[test]
+    fn filters_on_insert() {
+        let address: H160 = [2u8; 20].into();
+        let mut full = Blockchain::with_params(ChainParams::regtest());
+        let blocks = generate_blocks(&mut full, &mut Mempool::new(), 2, &address);
+        let wallet = SpvWallet::new(&[address]);
+        let stranger = SpvWallet::new(&[[5u8; 20].into()]);
+        for block in blocks.iter() {
+            let filter = full.filter(&block.hash()).unwrap();
+            assert!(wallet.matches(filter));
+            assert!(!stranger.matches(filter));
+        }
+
+        let mut light = Blockchain::light(ChainParams::regtest(), &[address]);
+        light.insert_header(&blocks[0].header);
+        assert!(light.filter(&blocks[0].hash()).is_none());
+    }
+
+    /// A child of the tip whose coinbase pays `value`, valid apart from the coinbase rule
+    fn block_with_coinbase(bc: &Blockchain, value: u8) -> Block {
+        let height = bc.get_length() + 1;
+        let coinbase = SignedTrans {
+            transaction: coin_base(&[1u8; 20].into(), value, height),
+            signature: Vec::new(),
+            public_key: Vec::new(),
+        };
+        Block::builder()
+            .height(height)
+            .parent(bc.tip())
+            .difficulty(bc.get_difficulty())
+            .timestamp(bc.blocks[&bc.tip()].0.header.get_create_time() + 1)
+            .transactions(vec![coinbase])
+            .build()
+    }
+
+    #[test]
+    fn timestamp_rules() {
+        let clock = Arc::new(MockClock::new(1_000_000));
+        let mut bc = Blockchain::with_params(ChainParams::regtest());
+        bc.set_clock(clock.clone());
+        generate_blocks(&mut bc, &mut Mempool::new(), 11, &[1u8; 20].into());
+        // the last 11 blocks have timestamps 1 to 11
+        assert_eq!(bc.median_time_past(&bc.tip()), 6);
+        let (tip, difficulty) = (bc.tip(), bc.get_difficulty());
+        let at = |timestamp: u128| Block::builder()
+            .height(12)
+            .parent(tip)
+            .difficulty(difficulty)
+            .timestamp(timestamp)
+            .build();
+        assert!(bc.validate_header(&at(6).header).is_err());
+        bc.validate_header(&at(7).header).unwrap();
+
+        let drift = bc.params().max_future_drift;
+        bc.validate_header(&at(1_000_000 + drift).header).unwrap();
+        assert!(bc.validate_header(&at(1_000_001 + drift).header).is_err());
+        // peers running ahead move the limit with them
+        for host in 1..6 {
+            bc.add_time_sample(IpAddr::from([127, 0, 0, host]), 1_060_000);
+        }
+        bc.validate_header(&at(1_000_001 + drift).header).unwrap();
+        clock.set(900_000);
+        assert!(bc.validate_header(&at(1_000_001 + drift).header).is_err());
+    }
+
+    #[test]
+    fn activation_at_height() {
+        let mut params = ChainParams::regtest();
+        params.deployments = vec![Deployment { name: COINBASE_SUBSIDY, activation: Activation::Height(3) }];
+        let mut bc = Blockchain::with_params(params);
+        generate_blocks(&mut bc, &mut Mempool::new(), 1, &[1u8; 20].into());
+        // height 2: not enforced yet
+        let greedy = block_with_coinbase(&bc, 50);
+        bc.validate(&greedy).unwrap();
+        bc.insert(&greedy);
+        // height 3: enforced
+        assert!(bc.validate(&block_with_coinbase(&bc, 50)).is_err());
+        bc.validate(&block_with_coinbase(&bc, 10)).unwrap();
+        assert_eq!(bc.block_version(&bc.tip()), VERSION_BITS_TOP);
+    }
+
+    #[test]
+    fn activation_by_signalling() {
+        let mut bc = Blockchain::with_params(ChainParams::regtest());
+        let mut mp = Mempool::new();
+        // the miner signals until activation; the first window holds blocks 0 to 143
+        generate_blocks(&mut bc, &mut mp, 142, &[1u8; 20].into());
+        assert!(!bc.is_active(COINBASE_SUBSIDY, &bc.tip()));
+        assert!(bc.blocks[&bc.tip()].0.header.signals(0));
+        generate_blocks(&mut bc, &mut mp, 1, &[1u8; 20].into());
+        assert!(bc.is_active(COINBASE_SUBSIDY, &bc.tip()));
+        assert_eq!(bc.block_version(&bc.tip()), VERSION_BITS_TOP);
+        assert!(bc.validate(&block_with_coinbase(&bc, 50)).is_err());
+        // and stays active through the following windows, which no longer signal
+        for _ in 0..150 {
+            let block = block_with_coinbase(&bc, 1);
+            bc.validate(&block).unwrap();
+            bc.insert(&block);
+        }
+        assert!(bc.is_active(COINBASE_SUBSIDY, &bc.tip()));
+
+        // a window without enough signals does not activate
+        let mut quiet = Blockchain::with_params(ChainParams::regtest());
+        for _ in 0..150 {
+            let block = block_with_coinbase(&quiet, 10);
+            quiet.validate(&block).unwrap();
+            quiet.insert(&block);
+        }
+        assert!(!quiet.is_active(COINBASE_SUBSIDY, &quiet.tip()));
+    }
+
+    #[test]
+    fn rejects_bad_version_and_height() {
+        let bc = Blockchain::with_params(ChainParams::regtest());
+        let block = block_with_coinbase(&bc, 10);
+        bc.validate(&block).unwrap();
+        let old_version = Block::builder().version(1).height(1).parent(bc.tip()).difficulty(bc.get_difficulty()).build();
+        assert!(bc.validate_header(&old_version.header).is_err());
+        let wrong_height = Block::builder().height(5).parent(bc.tip()).difficulty(bc.get_difficulty()).build();
+        assert!(bc.validate_header(&wrong_height.header).is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_transactions() {
+        let bc = Blockchain::with_params(ChainParams::regtest());
+        let coinbase = block_with_coinbase(&bc, 10).content[0].clone();
+        let payment = |byte: u8| SignedTrans {
+            transaction: coin_base(&[byte; 20].into(), 1, 1),
+            signature: Vec::new(),
+            public_key: Vec::new(),
+        };
+        let block = |content: Vec<SignedTrans>| Block::builder()
+            .height(1)
+            .parent(bc.tip())
+            .difficulty(bc.get_difficulty())
+            .timestamp(bc.blocks[&bc.tip()].0.header.get_create_time() + 1)
+            .transactions(content)
+            .build();
+        let honest = block(vec![coinbase.clone(), payment(2), payment(3)]);
+        let mutated = block(vec![coinbase, payment(2), payment(3), payment(3)]);
+        assert_eq!(honest.header.get_merkle_root(), mutated.header.get_merkle_root());
+        bc.validate(&honest).unwrap();
+        assert_eq!(bc.validate(&mutated), Err("block contains a duplicate transaction".to_string()));
+    }
+
+    #[test]
+    fn verify_block_transactions() {
+        let mut bc = Blockchain::with_params(ChainParams::regtest());
+        let key = key_pair::random();
+        let funding = coin_base(&H160::hash(key.public_key().as_ref()), 10, 1);
+        let mut block = block_with_coinbase(&bc, 10);
+        block.content[0].transaction = funding.clone();
+        bc.insert(&block);
+
+        let mut spend = Transaction {
+            id: [7u8; 32].into(),
+            inputs: vec![Input { index: 10, previous_hash: funding.id, witness: Vec::new() }],
+            outputs: vec![Output { balance: 10, address: [2u8; 20].into(), condition: Condition::PayToAddress }],
+        };
+        let witness = sign_input(&spend, 0, &funding.outputs, &key, SigHashType::ALL).unwrap();
+        spend.inputs[0].witness.push(witness);
+        let signed = SignedTrans {
+            signature: sign(&spend, &key),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction: spend,
+        };
+        let mut block = block_with_coinbase(&bc, 10);
+        block.content.push(signed);
+
+        let cache = SignatureCache::default();
+        let checks = bc.signature_checks(&block);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(cache.verify_batch(&checks), vec![true]);
+        bc.verify_transactions(&block, &cache).unwrap();
+        assert_eq!(cache.stats().hits, 1);
+
+        // the transaction-wide signature is ignored
+        let mut unsigned = block.clone();
+        unsigned.content[1].signature.clear();
+        unsigned.content[1].public_key.clear();
+        bc.verify_transactions(&unsigned, &DirectVerifier).unwrap();
+
+        block.content[1].transaction.inputs[0].witness[0].signature[0] ^= 1;
+        assert!(bc.verify_transactions(&block, &cache).is_err());
+    }
+
+    #[test]
+    fn spends_along_the_branch() {
+        let mut bc = Blockchain::with_params(ChainParams::regtest());
+        let genesis = bc.tip();
+        let main = block_with_coinbase(&bc, 10);
+        bc.insert(&main);
+        let main2 = block_with_coinbase(&bc, 10);
+        bc.insert(&main2);
+
+        // a branch off the genesis confirming a transaction of its own
+        let key = key_pair::random();
+        let branch_block = |parent: &Block, content: Vec<SignedTrans>| Block::builder()
+            .height(parent.header.get_height() + 1)
+            .parent(parent.hash())
+            .difficulty(parent.get_difficulty())
+            .timestamp(parent.header.get_create_time() + 1)
+            .transactions(content)
+            .build();
+        let mut coinbase = main.content[0].clone();
+        coinbase.transaction = coin_base(&H160::hash(key.public_key().as_ref()), 10, 1);
+        let fork = branch_block(&bc.blocks[&genesis].0, vec![coinbase]);
+        bc.insert(&fork);
+        let funding = fork.content[0].transaction.clone();
+        assert!(bc.spent_outputs(&funding.id).is_none());
+
+        let mut spend = Transaction {
+            id: [7u8; 32].into(),
+            inputs: vec![Input { index: 10, previous_hash: funding.id, witness: Vec::new() }],
+            outputs: vec![Output { balance: 10, address: [2u8; 20].into(), condition: Condition::PayToAddress }],
+        };
+        let witness = sign_input(&spend, 0, &funding.outputs, &key, SigHashType::ALL).unwrap();
+        spend.inputs[0].witness.push(witness);
+        let signed = SignedTrans {
+            signature: sign(&spend, &key),
+            public_key: key.public_key().as_ref().to_vec(),
+            transaction: spend,
+        };
+        let mut content = main2.content.clone();
+        content.push(signed.clone());
+        let fork2 = branch_block(&fork, content);
+        bc.verify_transactions(&fork2, &DirectVerifier).unwrap();
+
+        // not on the longest chain, nor on another branch
+        let mut on_main = block_with_coinbase(&bc, 10);
+        on_main.content.push(signed);
+        assert!(bc.verify_transactions(&on_main, &DirectVerifier).is_err());
+        assert!(bc.is_ancestor(&genesis, &fork2.header.parent));
+        assert!(!bc.is_ancestor(&main.hash(), &fork.hash()));
+        assert!(bc.is_ancestor(&main.hash(), &main2.hash()));
+        assert!(!bc.is_ancestor(&main2.hash(), &main.hash()));
+
+        // once the branch is the longest chain, the transaction is found there
+        bc.insert(&fork2);
+        let fork3 = branch_block(&fork2, Vec::new());
+        bc.insert(&fork3);
+        assert_eq!(bc.tip(), fork3.hash());
+        assert_eq!(bc.spent_outputs(&funding.id).unwrap().1, 1);
+    }
+
+    #[test]
+    fn unspent_and_find() {
+        let mut bc = Blockchain::with_params(ChainParams::regtest());
+        let address: H160 = [1u8; 20].into();
+        let first = block_with_coinbase(&bc, 10);
+        bc.insert(&first);
+        let second = block_with_coinbase(&bc, 10);
+        bc.insert(&second);
+        let ids: Vec<H256> = bc.unspent(&address).iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, vec![first.content[0].transaction.id, second.content[0].transaction.id]);
+        assert!(bc.unspent(&[2u8; 20].into()).is_empty());
+
+        let mut spend = block_with_coinbase(&bc, 10);
+        spend.content.push(SignedTrans {
+            transaction: Transaction {
+                id: [7u8; 32].into(),
+                inputs: vec![Input { index: 10, previous_hash: first.content[0].transaction.id, witness: Vec::new() }],
+                outputs: vec![Output { balance: 10, address: [2u8; 20].into(), condition: Condition::PayToAddress }],
+            },
+            signature: Vec::new(),
+            public_key: Vec::new(),
+        });
+        bc.insert(&spend);
+        assert_eq!(bc.unspent(&address).len(), 2);
+        assert_eq!(bc.unspent(&[2u8; 20].into()).len(), 1);
+
+        let (tx, hash, height) = bc.find_transaction(&spend.content[1].hash()).unwrap();
+        assert_eq!((tx.transaction.id, hash, height), (spend.content[1].transaction.id, spend.hash(), 3));
+        assert!(bc.find_transaction(&[9u8; 32].into()).is_none());
+    }
+
+    #[test]
+    fn fork_branches() {
+        let mut bc = Blockchain::new();
+        let genesis = bc.tip();
+        let main = generate_random_block(&genesis);
+        let main2 = generate_random_block(&main.hash());
+        let fork = generate_random_block(&genesis);
+        let fork2 = generate_random_block(&fork.hash());
+        let sibling = generate_random_block(&main.hash());
+        for block in [&main, &main2, &fork] {
+            bc.insert(block);
+        }
+        assert_eq!(bc.forks(), vec![vec![fork.hash()]]);
+        bc.insert(&fork2);
+        bc.insert(&sibling);
+        // fork2 did not outgrow the main chain, which keeps its first block at equal height
+        assert_eq!(bc.tip(), main2.hash());
+        let forks = bc.forks();
+        assert_eq!(forks.len(), 2);
+        assert!(forks.contains(&vec![fork.hash(), fork2.hash()]));
+        assert!(forks.contains(&vec![sibling.hash()]));
+
+        let tips = bc.tips();
+        assert_eq!(tips.len(), 3);
+        assert_eq!((tips[0].hash, tips[0].active, tips[0].branch_length), (main2.hash(), true, 0));
+        assert_eq!(tips[0].fork_point, main2.hash());
+        let fork_tip = tips.iter().find(|tip| tip.hash == fork2.hash()).unwrap();
+        assert_eq!((fork_tip.height, fork_tip.fork_point, fork_tip.branch_length), (2, genesis, 2));
+        assert!(!fork_tip.active);
+        let sibling_tip = tips.iter().find(|tip| tip.hash == sibling.hash()).unwrap();
+        assert_eq!((sibling_tip.fork_point, sibling_tip.branch_length), (main.hash(), 1));
+        assert_eq!(sibling_tip.work, tips[0].work);
+        assert_eq!(block_work(&[0xffu8; 32].into()), 1.0);
+    }
+
+    #[test]
+    fn chain_events() {
+        let mut bc = Blockchain::new();
+        let events = Arc::new(EventBus::default());
+        bc.set_events(events.clone());
+        let received = events.subscribe(&EventKind::ALL);
+        let genesis = bc.tip();
+        let main = generate_random_block(&genesis);
+        let fork = generate_random_block(&genesis);
+        let fork2 = generate_random_block(&fork.hash());
+        bc.insert(&main);
+        bc.insert(&fork);
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), vec![Event::BlockConnected { hash: main.hash(), height: 1 }]);
+
+        bc.insert(&fork2);
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), vec![
+            Event::BlockDisconnected { hash: main.hash(), height: 1 },
+            Event::BlockConnected { hash: fork.hash(), height: 1 },
+            Event::BlockConnected { hash: fork2.hash(), height: 2 },
+            Event::Reorg { old_tip: main.hash(), new_tip: fork2.hash(), fork_point: genesis, disconnected: 1, connected: 2 },
+        ]);
+
+        let mut mp = Mempool::new();
+        mp.set_events(events);
+        let tx = crate::signedtrans::generate_random_signedtrans();
+        mp.add(&tx);
+        mp.add(&tx);
+        mp.remove(&tx);
+        mp.remove(&tx);
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), vec![
+            Event::TxAddedToMempool { txid: tx.hash() },
+            Event::TxRemoved { txid: tx.hash() },
+        ]);
+    }
+
+    #[test]
+    fn save_load() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let block = generate_random_block(&genesis_hash);
+        let block2 = generate_random_block(&block.hash());
+        let fork = generate_random_block(&genesis_hash);
+        blockchain.insert(&block);
+        blockchain.insert(&block2);
+        blockchain.insert(&fork);
+
+        let path = std::env::temp_dir().join(format!("blocks-{}.dat", block2.hash()));
+        blockchain.save(&path).unwrap();
+        let mut loaded = Blockchain::new();
+        loaded.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.tip(), blockchain.tip());
+        assert_eq!(loaded.blocks.len(), 4);
+        assert_eq!(loaded.all_blocks_in_longest_chain(), blockchain.all_blocks_in_longest_chain());
+    }
 }
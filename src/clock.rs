@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Number of blocks whose median timestamp a new block must exceed
+pub const MEDIAN_TIME_SPAN: usize = 11;
+/// Peers needed before their clocks adjust ours
+const MIN_TIME_SAMPLES: usize = 5;
+/// Most peer clocks remembered
+const MAX_TIME_SAMPLES: usize = 200;
+/// Largest adjustment peers can make to our clock, in milliseconds
+const MAX_TIME_ADJUSTMENT: i128 = 70 * 60 * 1000;
+
+/// Source of the current time, in milliseconds since the Unix epoch.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> u128;
+}
+
+/// The local system clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis()
+    }
+}
+
+/// A clock that only moves when told to, for tests
+#[cfg(any(test, test_utilities))]
+#[derive(Default)]
+pub struct MockClock(std::sync::Mutex<u128>);
+
+#[cfg(any(test, test_utilities))]
+impl MockClock {
+    pub fn new(now: u128) -> Self {
+        MockClock(std::sync::Mutex::new(now))
+    }
+
+    pub fn set(&self, now: u128) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+impl Clock for MockClock {
+    fn now(&self) -> u128 {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Local time corrected by the median offset of the peers' clocks, as reported in their
+/// handshakes. Peers count by IP address, so one host opening many connections gets one sample.
+#[derive(Clone)]
+pub struct NetworkTime {
+    clock: Arc<dyn Clock>,
+    offsets: HashMap<IpAddr, i128>,
+}
+
+impl std::fmt::Debug for NetworkTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("NetworkTime")
+            .field("now", &self.clock.now())
+            .field("offsets", &self.offsets)
+            .finish()
+    }
+}
+
+impl Default for NetworkTime {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl NetworkTime {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        NetworkTime { clock, offsets: HashMap::new() }
+    }
+
+    /// Local time
+    pub fn local(&self) -> u128 {
+        self.clock.now()
+    }
+
+    /// Record the time a peer reported; later reports of the same peer replace earlier ones.
+    pub fn add_sample(&mut self, peer: IpAddr, peer_time: u128) {
+        if self.offsets.len() >= MAX_TIME_SAMPLES && !self.offsets.contains_key(&peer) {
+            return;
+        }
+        let offset = peer_time as i128 - self.clock.now() as i128;
+        self.offsets.insert(peer, offset);
+    }
+
+    /// Forget the time a peer reported, once it disconnected.
+    pub fn remove_sample(&mut self, peer: IpAddr) {
+        self.offsets.remove(&peer);
+    }
+
+    /// Median offset of the peers' clocks, zero until enough peers reported and capped at
+    /// `MAX_TIME_ADJUSTMENT`.
+    pub fn offset(&self) -> i128 {
+        if self.offsets.len() < MIN_TIME_SAMPLES {
+            return 0;
+        }
+        let mut offsets: Vec<i128> = self.offsets.values().cloned().collect();
+        offsets.sort_unstable();
+        offsets[offsets.len() / 2].clamp(-MAX_TIME_ADJUSTMENT, MAX_TIME_ADJUSTMENT)
+    }
+
+    /// Network-adjusted time
+    pub fn adjusted(&self) -> u128 {
+        (self.clock.now() as i128 + self.offset()).max(0) as u128
+    }
+}
+
+/// Median of the timestamps of up to `MEDIAN_TIME_SPAN` blocks.
+pub fn median_time(timestamps: &[u128]) -> u128 {
+    let mut sorted = timestamps.to_vec();
+    sorted.sort_unstable();
+    sorted.get(sorted.len() / 2).cloned().unwrap_or(0)
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    fn peer(i: u8) -> IpAddr {
+        IpAddr::from([127, 0, 0, 1 + i])
+    }
+
+    #[test]
+    fn adjusts_after_enough_peers() {
+        let clock = Arc::new(MockClock::new(1_000_000));
+        let mut time = NetworkTime::new(clock.clone());
+        for i in 0..4 {
+            time.add_sample(peer(i), 1_000_000 + 5_000);
+        }
+        assert_eq!(time.adjusted(), 1_000_000);
+        time.add_sample(peer(4), 1_000_000 - 1_000);
+        assert_eq!(time.offset(), 5_000);
+        assert_eq!(time.adjusted(), 1_005_000);
+        clock.set(2_000_000);
+        assert_eq!(time.adjusted(), 2_005_000);
+        // a peer reporting again replaces its sample
+        time.add_sample(peer(0), 1_000);
+        assert_eq!(time.offset(), 5_000);
+        // and a disconnected peer no longer counts
+        time.remove_sample(peer(1));
+        assert_eq!(time.adjusted(), 2_000_000);
+    }
+
+    #[test]
+    fn adjustment_is_capped() {
+        let mut time = NetworkTime::new(Arc::new(MockClock::new(0)));
+        for i in 0..5 {
+            time.add_sample(peer(i), 10 * 3_600_000);
+        }
+        assert_eq!(time.offset(), MAX_TIME_ADJUSTMENT);
+    }
+
+    #[test]
+    fn median() {
+        assert_eq!(median_time(&[]), 0);
+        assert_eq!(median_time(&[5]), 5);
+        assert_eq!(median_time(&[9, 1, 5, 3, 7]), 5);
+    }
+}
@@ -0,0 +1,425 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use crate::crypto::batch::{SignatureCheck, SignatureVerifier};
+use crate::crypto::hash::{H160, H256};
+use crate::encoding::{Decode, Encode, Reader};
+
+/// Most timelocks nested in one condition
+const MAX_DEPTH: usize = 4;
+/// Most keys in a multisig condition
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+/// What it takes to spend an output. Conditions are evaluated, not executed: there are no loops,
+/// nesting is bounded and so is the number of signatures to check. Both bounds, and distinct
+/// multisig keys, hold for the serde encoding of P2P messages as much as for the consensus
+/// encoding.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// A signature by the key hashing to the output's address
+    #[default]
+    PayToAddress,
+    /// Signatures by at least `threshold` distinct keys of `keys`
+    Multisig { threshold: u8, keys: Vec<Vec<u8>> },
+    /// `condition`, in blocks at `height` or above
+    AbsoluteTimelock { height: u32, condition: Box<Condition> },
+    /// `condition`, in blocks at least `blocks` blocks after the one confirming the output
+    RelativeTimelock { blocks: u32, condition: Box<Condition> },
+}
+
+/// `Condition` as serde derives it, deserialized before its bounds are checked
+#[derive(Deserialize)]
+#[serde(rename = "Condition")]
+enum UncheckedCondition {
+    PayToAddress,
+    Multisig { threshold: u8, keys: Vec<Vec<u8>> },
+    AbsoluteTimelock { height: u32, condition: Box<Condition> },
+    RelativeTimelock { blocks: u32, condition: Box<Condition> },
+}
+
+thread_local! {
+    /// Conditions being deserialized on this thread, each nested in the previous one
+    static DESERIALIZE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // refuse to go deeper before the nested condition is read, so that the recursion is bounded
+        let depth = DESERIALIZE_DEPTH.with(Cell::get);
+        if depth > MAX_DEPTH {
+            return Err(de::Error::custom("condition nested too deeply"));
+        }
+        DESERIALIZE_DEPTH.with(|d| d.set(depth + 1));
+        let unchecked = UncheckedCondition::deserialize(deserializer);
+        DESERIALIZE_DEPTH.with(|d| d.set(depth));
+        Ok(match unchecked? {
+            UncheckedCondition::PayToAddress => Condition::PayToAddress,
+            UncheckedCondition::Multisig { keys, .. } if keys.len() > MAX_MULTISIG_KEYS => {
+                return Err(de::Error::custom("too many multisig keys"));
+            }
+            UncheckedCondition::Multisig { keys, .. } if has_duplicates(&keys) => {
+                return Err(de::Error::custom("duplicate multisig key"));
+            }
+            UncheckedCondition::Multisig { threshold, keys } => Condition::Multisig { threshold, keys },
+            UncheckedCondition::AbsoluteTimelock { height, condition } => Condition::AbsoluteTimelock { height, condition },
+            UncheckedCondition::RelativeTimelock { blocks, condition } => Condition::RelativeTimelock { blocks, condition },
+        })
+    }
+}
+
+/// Which parts of the spending transaction a signature covers: a base type (`ALL`, `NONE` or
+/// `SINGLE`), optionally combined with `ANYONECANPAY`. The input being signed and the outputs it
+/// spends are always covered.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "u8", into = "u8")]
+pub struct SigHashType(u8);
+
+impl TryFrom<u8> for SigHashType {
+    type Error = String;
+
+    fn try_from(byte: u8) -> Result<Self, String> {
+        Self::from_byte(byte)
+    }
+}
+
+impl From<SigHashType> for u8 {
+    fn from(sighash_type: SigHashType) -> u8 {
+        sighash_type.0
+    }
+}
+
+impl SigHashType {
+    /// Every output
+    pub const ALL: SigHashType = SigHashType(1);
+    /// No output, so that anyone can decide where the coins go
+    pub const NONE: SigHashType = SigHashType(2);
+    /// The output with the same index as the input only
+    pub const SINGLE: SigHashType = SigHashType(3);
+    /// Flag: the signed input only, so that others can add theirs
+    pub const ANYONECANPAY: u8 = 0x80;
+
+    pub fn from_byte(byte: u8) -> Result<Self, String> {
+        match byte & !Self::ANYONECANPAY {
+            1..=3 => Ok(SigHashType(byte)),
+            _ => Err(format!("invalid sighash type {:#x}", byte)),
+        }
+    }
+
+    /// This type with the `ANYONECANPAY` flag set
+    pub fn anyone_can_pay(self) -> Self {
+        SigHashType(self.0 | Self::ANYONECANPAY)
+    }
+
+    pub fn is_anyone_can_pay(self) -> bool {
+        self.0 & Self::ANYONECANPAY != 0
+    }
+
+    /// The base type, without flags
+    pub fn base(self) -> Self {
+        SigHashType(self.0 & !Self::ANYONECANPAY)
+    }
+
+    pub fn as_byte(self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for SigHashType {
+    fn default() -> Self {
+        SigHashType::ALL
+    }
+}
+
+/// A signature in an input witness, with the public key that made it and what it covers.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct WitnessSignature {
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub sighash_type: SigHashType,
+}
+
+/// What evaluating a condition needs to know about the spend.
+pub struct SpendContext<'a> {
+    /// Hash of the spending transaction, witnesses included
+    pub txid: H256,
+    /// Digest a witness signature of the given type signs
+    pub sighash: &'a dyn Fn(SigHashType) -> Result<H256, String>,
+    /// Checks the witness signatures
+    pub verifier: &'a dyn SignatureVerifier,
+    /// Address of the output being spent
+    pub address: H160,
+    /// Height of the block confirming the output being spent
+    pub confirmed_height: u32,
+    /// Height of the block the spending transaction goes in
+    pub spend_height: u32,
+}
+
+impl Condition {
+    /// Check that `witness` satisfies the condition.
+    pub fn evaluate(&self, witness: &[WitnessSignature], ctx: &SpendContext) -> Result<(), String> {
+        match self {
+            Condition::PayToAddress => {
+                let signed = witness.iter().any(|sig| {
+                    H160::hash(&sig.public_key) == ctx.address && check_signature(sig, ctx)
+                });
+                if signed {
+                    Ok(())
+                } else {
+                    Err(format!("no valid signature for address {}", ctx.address))
+                }
+            }
+            Condition::Multisig { threshold, keys } => {
+                if *threshold == 0 || *threshold as usize > keys.len() || keys.len() > MAX_MULTISIG_KEYS || has_duplicates(keys) {
+                    return Err(format!("invalid {}-of-{} multisig", threshold, keys.len()));
+                }
+                let signed = keys.iter()
+                    .filter(|key| witness.iter().any(|sig| &sig.public_key == *key && check_signature(sig, ctx)))
+                    .count();
+                if signed >= *threshold as usize {
+                    Ok(())
+                } else {
+                    Err(format!("{} of the {} signatures needed", signed, threshold))
+                }
+            }
+            Condition::AbsoluteTimelock { height, condition } => {
+                if ctx.spend_height < *height {
+                    return Err(format!("locked until height {}", height));
+                }
+                condition.evaluate(witness, ctx)
+            }
+            Condition::RelativeTimelock { blocks, condition } => {
+                if ctx.spend_height < ctx.confirmed_height.saturating_add(*blocks) {
+                    return Err(format!("locked for {} blocks after confirmation", blocks));
+                }
+                condition.evaluate(witness, ctx)
+            }
+        }
+    }
+
+    /// Address to pay to for outputs with this condition, other than pay-to-address, so that
+    /// the output can still be found by address.
+    pub fn address(&self) -> H160 {
+        H160::hash(&self.encode())
+    }
+
+    fn decode_nested(reader: &mut Reader, depth: usize) -> Result<Self, String> {
+        if depth > MAX_DEPTH {
+            return Err("condition nested too deeply".to_string());
+        }
+        match u8::decode_from(reader)? {
+            0 => Ok(Condition::PayToAddress),
+            1 => {
+                let threshold = u8::decode_from(reader)?;
+                let keys = Vec::<Vec<u8>>::decode_from(reader)?;
+                if keys.len() > MAX_MULTISIG_KEYS {
+                    return Err("too many multisig keys".to_string());
+                }
+                if has_duplicates(&keys) {
+                    return Err("duplicate multisig key".to_string());
+                }
+                Ok(Condition::Multisig { threshold, keys })
+            }
+            2 => {
+                let height = u32::decode_from(reader)?;
+                let condition = Box::new(Self::decode_nested(reader, depth + 1)?);
+                Ok(Condition::AbsoluteTimelock { height, condition })
+            }
+            3 => {
+                let blocks = u32::decode_from(reader)?;
+                let condition = Box::new(Self::decode_nested(reader, depth + 1)?);
+                Ok(Condition::RelativeTimelock { blocks, condition })
+            }
+            tag => Err(format!("unknown condition {}", tag)),
+        }
+    }
+}
+
+fn has_duplicates(keys: &[Vec<u8>]) -> bool {
+    let mut seen = HashSet::new();
+    !keys.iter().all(|key| seen.insert(key))
+}
+
+fn check_signature(sig: &WitnessSignature, ctx: &SpendContext) -> bool {
+    let message = match (ctx.sighash)(sig.sighash_type) {
+        Ok(message) => message,
+        Err(_) => return false,
+    };
+    ctx.verifier.verify(&SignatureCheck {
+        txid: ctx.txid,
+        public_key: sig.public_key.clone(),
+        message,
+        signature: sig.signature.clone(),
+    })
+}
+
+/// Encoded as a tag byte (0 to 3 in declaration order), then the fields in declaration order
+impl Encode for Condition {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Condition::PayToAddress => out.push(0),
+            Condition::Multisig { threshold, keys } => {
+                out.push(1);
+                threshold.encode_to(out);
+                keys.encode_to(out);
+            }
+            Condition::AbsoluteTimelock { height, condition } => {
+                out.push(2);
+                height.encode_to(out);
+                condition.encode_to(out);
+            }
+            Condition::RelativeTimelock { blocks, condition } => {
+                out.push(3);
+                blocks.encode_to(out);
+                condition.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Condition {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        Self::decode_nested(reader, 0)
+    }
+}
+
+/// Encoded as the public key, the signature, then the sighash type byte
+impl Encode for WitnessSignature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.public_key.encode_to(out);
+        self.signature.encode_to(out);
+        self.sighash_type.as_byte().encode_to(out);
+    }
+}
+
+impl Decode for WitnessSignature {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let public_key = Vec::<u8>::decode_from(reader)?;
+        let signature = Vec::<u8>::decode_from(reader)?;
+        let sighash_type = SigHashType::from_byte(u8::decode_from(reader)?)?;
+        Ok(WitnessSignature { public_key, signature, sighash_type })
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use crate::crypto::batch::DirectVerifier;
+    use crate::crypto::key_pair;
+
+    fn message(sighash_type: SigHashType) -> Result<H256, String> {
+        Ok([sighash_type.as_byte(); 32].into())
+    }
+
+    fn witness(keys: &[&Ed25519KeyPair], sighash_type: SigHashType) -> Vec<WitnessSignature> {
+        keys.iter()
+            .map(|key| WitnessSignature {
+                public_key: key.public_key().as_ref().to_vec(),
+                signature: key.sign(message(SigHashType::ALL).unwrap().as_ref()).as_ref().to_vec(),
+                sighash_type,
+            })
+            .collect()
+    }
+
+    fn context(address: H160, spend_height: u32) -> SpendContext<'static> {
+        SpendContext { txid: [9u8; 32].into(), sighash: &message, verifier: &DirectVerifier, address, confirmed_height: 10, spend_height }
+    }
+
+    #[test]
+    fn pay_to_address() {
+        let key = key_pair::random();
+        let other = key_pair::random();
+        let address = H160::hash(key.public_key().as_ref());
+        let condition = Condition::PayToAddress;
+        condition.evaluate(&witness(&[&key], SigHashType::ALL), &context(address, 11)).unwrap();
+        assert!(condition.evaluate(&witness(&[&other], SigHashType::ALL), &context(address, 11)).is_err());
+        // a signature relabelled with another type
+        assert!(condition.evaluate(&witness(&[&key], SigHashType::NONE), &context(address, 11)).is_err());
+    }
+
+    #[test]
+    fn multisig() {
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let condition = Condition::Multisig {
+            threshold: 2,
+            keys: keys.iter().map(|key| key.public_key().as_ref().to_vec()).collect(),
+        };
+        let ctx = context(condition.address(), 11);
+        condition.evaluate(&witness(&[&keys[0], &keys[2]], SigHashType::ALL), &ctx).unwrap();
+        assert!(condition.evaluate(&witness(&[&keys[1]], SigHashType::ALL), &ctx).is_err());
+        // the same key twice counts once
+        assert!(condition.evaluate(&witness(&[&keys[1], &keys[1]], SigHashType::ALL), &ctx).is_err());
+        let stranger = key_pair::random();
+        assert!(condition.evaluate(&witness(&[&keys[1], &stranger], SigHashType::ALL), &ctx).is_err());
+
+        // a key listed twice does not count twice
+        let repeated = Condition::Multisig {
+            threshold: 2,
+            keys: vec![keys[0].public_key().as_ref().to_vec(); 2],
+        };
+        let ctx = context(repeated.address(), 11);
+        assert!(repeated.evaluate(&witness(&[&keys[0]], SigHashType::ALL), &ctx).is_err());
+        assert!(Condition::decode(&repeated.encode()).is_err());
+        assert!(bincode::deserialize::<Condition>(&bincode::serialize(&repeated).unwrap()).is_err());
+    }
+
+    #[test]
+    fn timelocks() {
+        let key = key_pair::random();
+        let address = H160::hash(key.public_key().as_ref());
+        let signed = witness(&[&key], SigHashType::ALL);
+        let absolute = Condition::AbsoluteTimelock { height: 20, condition: Box::new(Condition::PayToAddress) };
+        assert!(absolute.evaluate(&signed, &context(address, 19)).is_err());
+        absolute.evaluate(&signed, &context(address, 20)).unwrap();
+        let relative = Condition::RelativeTimelock { blocks: 5, condition: Box::new(Condition::PayToAddress) };
+        assert!(relative.evaluate(&signed, &context(address, 14)).is_err());
+        relative.evaluate(&signed, &context(address, 15)).unwrap();
+        assert!(relative.evaluate(&[], &context(address, 15)).is_err());
+    }
+
+    #[test]
+    fn encoding_bounds_nesting() {
+        let mut condition = Condition::Multisig { threshold: 1, keys: vec![vec![1, 2, 3]] };
+        for height in 0..MAX_DEPTH as u32 {
+            condition = Condition::AbsoluteTimelock { height, condition: Box::new(condition) };
+        }
+        assert_eq!(Condition::decode(&condition.encode()).unwrap(), condition);
+        let deeper = Condition::RelativeTimelock { blocks: 1, condition: Box::new(condition) };
+        assert!(Condition::decode(&deeper.encode()).is_err());
+    }
+
+    #[test]
+    fn deserializing_bounds_nesting() {
+        let mut condition = Condition::Multisig { threshold: 1, keys: vec![vec![1, 2, 3]] };
+        for height in 0..MAX_DEPTH as u32 {
+            condition = Condition::AbsoluteTimelock { height, condition: Box::new(condition) };
+        }
+        let decoded: Condition = bincode::deserialize(&bincode::serialize(&condition).unwrap()).unwrap();
+        assert_eq!(decoded, condition);
+        let deeper = Condition::RelativeTimelock { blocks: 1, condition: Box::new(condition.clone()) };
+        assert!(bincode::deserialize::<Condition>(&bincode::serialize(&deeper).unwrap()).is_err());
+        // the depth is back to zero after the error
+        assert!(bincode::deserialize::<Condition>(&bincode::serialize(&condition).unwrap()).is_ok());
+
+        let wide = Condition::Multisig { threshold: 1, keys: vec![vec![1]; MAX_MULTISIG_KEYS + 1] };
+        assert!(bincode::deserialize::<Condition>(&bincode::serialize(&wide).unwrap()).is_err());
+        let json = serde_json::to_string(&condition).unwrap();
+        assert_eq!(serde_json::from_str::<Condition>(&json).unwrap(), condition);
+    }
+
+    #[test]
+    fn sighash_types() {
+        let single = SigHashType::from_byte(3).unwrap().anyone_can_pay();
+        assert_eq!(single.as_byte(), 0x83);
+        assert!(single.is_anyone_can_pay());
+        assert_eq!(single.base(), SigHashType::SINGLE);
+        assert_eq!(SigHashType::from_byte(0x83).unwrap(), single);
+        for byte in [0, 4, 0x41, 0x80, 0xff] {
+            assert!(SigHashType::from_byte(byte).is_err());
+            // P2P messages hold the same byte
+            assert!(bincode::deserialize::<SigHashType>(&[byte]).is_err());
+        }
+        assert_eq!(bincode::deserialize::<SigHashType>(&bincode::serialize(&single).unwrap()).unwrap(), single);
+    }
+}
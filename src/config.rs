@@ -0,0 +1,202 @@
+use serde::{Serialize, Deserialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use crate::address::Address;
+use crate::crypto::hash::H160;
+use crate::crypto::mnemonic::Mnemonic;
+use crate::crypto::sigcache::DEFAULT_SIGNATURE_CACHE_SIZE;
+use crate::params::Network;
+
+/// Node configuration, read from a TOML file. Missing keys take their default values and
+/// command-line flags override whatever the file says.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: Network,
+    /// P2P server address, the network's default port on localhost if not set
+    pub p2p_addr: Option<SocketAddr>,
+    /// API server address, the network's default port on localhost if not set
+    pub api_addr: Option<SocketAddr>,
+    pub peers: Vec<SocketAddr>,
+    pub p2p_workers: usize,
+    pub data_dir: PathBuf,
+    /// One of error, warn, info, debug, trace
+    pub log_level: String,
+    /// Run as a light client, syncing headers and the transactions of `watch_addresses` only
+    pub light: bool,
+    /// Addresses whose transactions a light client tracks
+    pub watch_addresses: Vec<String>,
+    /// Valid signatures remembered, so that transactions are not verified again in blocks
+    pub signature_cache_size: usize,
+    pub miner: MinerConfig,
+    pub generator: GeneratorConfig,
+    pub mempool: MempoolConfig,
+    pub wallet: WalletConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinerConfig {
+    pub auto_start: bool,
+    pub lambda: u64,
+    /// Address the coinbase pays to, a fresh wallet address if not set
+    pub payout_address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorConfig {
+    pub auto_start: bool,
+    pub lambda: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    /// Maximum number of transactions, unlimited if not set
+    pub max_size: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalletConfig {
+    /// Phrase of the wallet seed, a fresh one if not set
+    pub mnemonic: Option<String>,
+    /// Extra word stretched into the seed along with the phrase
+    pub passphrase: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            network: Network::Mainnet,
+            p2p_addr: None,
+            api_addr: None,
+            peers: Vec::new(),
+            p2p_workers: 4,
+            data_dir: PathBuf::from("data"),
+            log_level: "error".to_string(),
+            light: false,
+            watch_addresses: Vec::new(),
+            signature_cache_size: DEFAULT_SIGNATURE_CACHE_SIZE,
+            miner: MinerConfig::default(),
+            generator: GeneratorConfig::default(),
+            mempool: MempoolConfig::default(),
+            wallet: WalletConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Read a configuration file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("error parsing {}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the values that serde cannot check by itself.
+    pub fn validate(&self) -> Result<(), String> {
+        self.verbosity()?;
+        self.payout_address()?;
+        self.watch_addresses()?;
+        self.mnemonic()?;
+        if self.light && self.miner.auto_start {
+            return Err("a light client cannot mine".to_string());
+        }
+        if self.p2p_workers == 0 {
+            return Err("p2p_workers must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// The logger verbosity matching `log_level`.
+    pub fn verbosity(&self) -> Result<usize, String> {
+        match self.log_level.as_str() {
+            "error" => Ok(0),
+            "warn" => Ok(1),
+            "info" => Ok(2),
+            "debug" => Ok(3),
+            "trace" => Ok(4),
+            other => Err(format!("unknown log level {}", other)),
+        }
+    }
+
+    pub fn payout_address(&self) -> Result<Option<H160>, String> {
+        match &self.miner.payout_address {
+            Some(address) => Address::parse_for(address, self.network)
+                .map(Some)
+                .map_err(|e| format!("error parsing payout address: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn watch_addresses(&self) -> Result<Vec<H160>, String> {
+        self.watch_addresses
+            .iter()
+            .map(|address| {
+                Address::parse_for(address, self.network)
+                    .map_err(|e| format!("error parsing watch address {}: {}", address, e))
+            })
+            .collect()
+    }
+
+    pub fn mnemonic(&self) -> Result<Option<Mnemonic>, String> {
+        match &self.wallet.mnemonic {
+            Some(phrase) => Mnemonic::parse(phrase)
+                .map(Some)
+                .map_err(|e| format!("error parsing wallet mnemonic: {}", e)),
+            None => Ok(None),
+        }
+    }
+
+    /// The effective configuration as TOML, in the format `load` reads.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).unwrap()
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_file_uses_defaults() {
+        let config: Config = toml::from_str("network = \"regtest\"\n[miner]\nlambda = 10\n").unwrap();
+        assert_eq!(config.network, Network::Regtest);
+        assert_eq!(config.miner.lambda, 10);
+        assert!(!config.miner.auto_start);
+        assert_eq!(config.p2p_workers, 4);
+    }
+
+    #[test]
+    fn dump_round_trip() {
+        let config = Config {
+            api_addr: Some("127.0.0.1:7001".parse().unwrap()),
+            peers: vec!["127.0.0.1:6001".parse().unwrap()],
+            mempool: MempoolConfig { max_size: Some(100) },
+            ..Default::default()
+        };
+        let parsed: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(parsed.to_toml(), config.to_toml());
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        let config = Config { log_level: "loud".to_string(), ..Default::default() };
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.miner.payout_address = Some("xyz".to_string());
+        assert!(config.validate().is_err());
+        let mut config = Config { light: true, ..Default::default() };
+        config.miner.auto_start = true;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.wallet.mnemonic = Some("abandon abandon abandon".to_string());
+        assert!(config.validate().is_err());
+        assert!(toml::from_str::<Config>("unknown_key = 1").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! Signature verification in batches. The signatures of a block or of a batch of transactions
+//! are checked in parallel before the chain lock is taken; the valid ones go to the
+//! `SignatureCache`, so that the checks done under the lock are lookups.
+use std::thread;
+use crate::crypto::hash::H256;
+
+/// A signature to check: the transaction carrying it, the key, the signed message and the
+/// signature.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SignatureCheck {
+    /// Hash of the signed transaction, witnesses included
+    pub txid: H256,
+    pub public_key: Vec<u8>,
+    pub message: H256,
+    pub signature: Vec<u8>,
+}
+
+impl SignatureCheck {
+    /// Verify the Ed25519 signature.
+    pub fn verify(&self) -> bool {
+        let public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &self.public_key);
+        public_key.verify(self.message.as_ref(), &self.signature).is_ok()
+    }
+}
+
+/// Something that can tell whether a signature is valid.
+pub trait SignatureVerifier: Sync {
+    fn verify(&self, check: &SignatureCheck) -> bool;
+}
+
+/// Verifies every signature on the spot
+pub struct DirectVerifier;
+
+impl SignatureVerifier for DirectVerifier {
+    fn verify(&self, check: &SignatureCheck) -> bool {
+        check.verify()
+    }
+}
+
+/// Verify `checks` spread over one thread per core. Returns whether each is valid.
+pub fn verify_parallel(checks: &[SignatureCheck]) -> Vec<bool> {
+    if checks.is_empty() {
+        return Vec::new();
+    }
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = checks.len().div_ceil(threads);
+    crossbeam::scope(|scope| {
+        let handles: Vec<_> = checks.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move |_| chunk.iter().map(SignatureCheck::verify).collect::<Vec<bool>>()))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    }).unwrap()
+}
+
+/// `n` valid signature checks by fresh keys, with distinct transactions and messages
+#[cfg(any(test, test_utilities))]
+pub fn generate_random_checks(n: usize) -> Vec<SignatureCheck> {
+    use ring::signature::KeyPair;
+    (0..n).map(|i| {
+        let key = crate::crypto::key_pair::random();
+        let message = H256::from([i as u8; 32]);
+        SignatureCheck {
+            txid: H256::from([i as u8 + 1; 32]),
+            public_key: key.public_key().as_ref().to_vec(),
+            message,
+            signature: key.sign(message.as_ref()).as_ref().to_vec(),
+        }
+    }).collect()
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_matches_serial() {
+        let mut checks = generate_random_checks(37);
+        checks[5].signature[0] ^= 1;
+        checks[30].message = H256::from([0xee; 32]);
+        let expected: Vec<bool> = checks.iter().map(SignatureCheck::verify).collect();
+        assert_eq!(verify_parallel(&checks), expected);
+        assert_eq!(expected.iter().filter(|ok| !**ok).count(), 2);
+    }
+}
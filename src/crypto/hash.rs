@@ -30,6 +30,27 @@ impl Hashable for H256 {
     }
 }
 
+/// Tags of the hashes used by consensus, so that a value hashed in one role can never be taken
+/// for a value of another role.
+pub mod tags {
+    pub const HEADER: &str = "bitcoin/header";
+    pub const TRANSACTION: &str = "bitcoin/transaction";
+    pub const COINBASE: &str = "bitcoin/coinbase";
+    pub const MERKLE_LEAF: &str = "bitcoin/merkle-leaf";
+    pub const MERKLE_NODE: &str = "bitcoin/merkle-node";
+    pub const SIGHASH: &str = "bitcoin/sighash";
+}
+
+/// SHA256 of `data` prefixed with the SHA256 of `tag` twice, as in BIP340.
+pub fn tagged_hash(tag: &str, data: &[u8]) -> H256 {
+    let tag_hash = ring::digest::digest(&ring::digest::SHA256, tag.as_bytes());
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(tag_hash.as_ref());
+    ctx.update(tag_hash.as_ref());
+    ctx.update(data);
+    ctx.finish().into()
+}
+
 pub fn generate_rand_hash256() -> H256 {
     let mut rng = rand::thread_rng();
     let random_bytes: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
@@ -65,6 +86,20 @@ impl std::fmt::Display for H256 {
     }
 }
 
+impl std::str::FromStr for H256 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|e| e.to_string())?;
+        if bytes.len() != 32 {
+            return Err(format!("expected 32 bytes, got {}", bytes.len()));
+        }
+        let mut raw = [0u8; 32];
+        raw.copy_from_slice(&bytes);
+        Ok(H256(raw))
+    }
+}
+
 impl std::fmt::Debug for H256 {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -75,6 +110,29 @@ impl std::fmt::Debug for H256 {
     }
 }
 
+impl std::fmt::Display for H160 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:>02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for H160 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|e| e.to_string())?;
+        if bytes.len() != 20 {
+            return Err(format!("expected 20 bytes, got {}", bytes.len()));
+        }
+        let mut raw = [0u8; 20];
+        raw.copy_from_slice(&bytes);
+        Ok(H160(raw))
+    }
+}
+
 impl std::fmt::Debug for H160 {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
@@ -91,6 +149,12 @@ impl std::convert::AsRef<[u8]> for H256 {
     }
 }
 
+impl std::convert::AsRef<[u8]> for H160 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl std::convert::From<&[u8; 32]> for H256 {
     fn from(input: &[u8; 32]) -> H256 {
         let mut buffer: [u8; 32] = [0; 32];
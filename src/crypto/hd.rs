@@ -0,0 +1,118 @@
+//! Hierarchical deterministic keys for Ed25519, as in SLIP-0010: every key of a wallet derives
+//! from one seed, so backing up the seed backs up all of them. Ed25519 only has hardened
+//! derivation, which needs the parent private key.
+use ring::hmac;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+/// Indices at or above this are hardened, written with a `'` in paths
+pub const HARDENED: u32 = 0x8000_0000;
+
+/// A private key with the chain code its children derive from.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ExtendedKey {
+    pub private_key: [u8; 32],
+    pub chain_code: [u8; 32],
+}
+
+impl std::fmt::Debug for ExtendedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // keep private keys out of logs
+        f.debug_struct("ExtendedKey")
+            .field("public_key", &hex::encode(self.public_key()))
+            .finish()
+    }
+}
+
+impl ExtendedKey {
+    /// The master key of a seed.
+    pub fn master(seed: &[u8]) -> Self {
+        Self::from_hmac(b"ed25519 seed", &[seed])
+    }
+
+    /// The hardened child `index`, which must be at least `HARDENED`.
+    pub fn child(&self, index: u32) -> Result<Self, String> {
+        if index < HARDENED {
+            return Err(format!("ed25519 keys only have hardened children, not {}", index));
+        }
+        Ok(Self::from_hmac(&self.chain_code, &[&[0], &self.private_key, &index.to_be_bytes()]))
+    }
+
+    /// The key at `path` below this one, such as `m/44'/0'/1'`.
+    pub fn derive(&self, path: &str) -> Result<Self, String> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("m") {
+            return Err(format!("derivation path {} does not start with m", path));
+        }
+        parts.try_fold(self.clone(), |key, part| {
+            let index = part.strip_suffix('\'')
+                .or_else(|| part.strip_suffix('h'))
+                .ok_or_else(|| format!("path component {} is not hardened", part))?
+                .parse::<u32>()
+                .ok()
+                .filter(|index| *index < HARDENED)
+                .ok_or_else(|| format!("invalid path component {}", part))?;
+            key.child(index + HARDENED)
+        })
+    }
+
+    pub fn key_pair(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.private_key).unwrap()
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.key_pair().public_key().as_ref().to_vec()
+    }
+
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut ctx = hmac::Context::with_key(&hmac::Key::new(hmac::HMAC_SHA512, key));
+        for part in data {
+            ctx.update(part);
+        }
+        let tag = ctx.sign();
+        let mut private_key = [0u8; 32];
+        let mut chain_code = [0u8; 32];
+        private_key.copy_from_slice(&tag.as_ref()[..32]);
+        chain_code.copy_from_slice(&tag.as_ref()[32..]);
+        ExtendedKey { private_key, chain_code }
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    fn check(key: &ExtendedKey, chain_code: &str, private_key: &str, public_key: &str) {
+        assert_eq!(hex::encode(key.chain_code), chain_code);
+        assert_eq!(hex::encode(key.private_key), private_key);
+        // SLIP-0010 prefixes ed25519 public keys with a zero byte
+        assert_eq!(format!("00{}", hex::encode(key.public_key())), public_key);
+    }
+
+    #[test]
+    fn slip10_vector() {
+        let master = ExtendedKey::master(&hex::decode("000102030405060708090a0b0c0d0e0f").unwrap());
+        check(&master,
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb",
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+            "00a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed");
+        check(&master.derive("m/0'").unwrap(),
+            "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69",
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+            "008c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c");
+        check(&master.derive("m/0'/1'").unwrap(),
+            "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14",
+            "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+            "001932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187");
+    }
+
+    #[test]
+    fn rejects_bad_paths() {
+        let master = ExtendedKey::master(&[1u8; 32]);
+        assert_eq!(master.derive("m").unwrap(), master);
+        assert_eq!(master.derive("m/7h").unwrap(), master.child(HARDENED + 7).unwrap());
+        assert!(master.child(7).is_err());
+        for path in ["", "44'", "m/44", "m/x'", "m/2147483648'"] {
+            assert!(master.derive(path).is_err(), "{}", path);
+        }
+    }
+}
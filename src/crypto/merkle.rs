@@ -1,104 +1,175 @@
-use super::hash::{Hashable, H256};
+use super::hash::{tagged_hash, tags, Hashable, H256};
 
+/// A Merkle tree, stored level by level: `levels[0]` holds the leaf hashes and the last level
+/// holds the root alone. A level of odd length pairs its last node with itself. Leaves and inner
+/// nodes are hashed under different tags, so an inner node cannot be passed off as a leaf.
 #[derive(Debug, Default, Clone)]
-struct MerkleTreeNode {
-    left: Option<Box<MerkleTreeNode>>,
-    right: Option<Box<MerkleTreeNode>>,
-    hash: H256,
+pub struct MerkleTree {
+    levels: Vec<Vec<H256>>,
 }
 
-/// A Merkle tree.
-#[derive(Debug, Default)]
-pub struct MerkleTree {
-    root: MerkleTreeNode,
-    level_count: usize, // how many levels the tree has
+/// Given the hash of a datum, compute the hash of its leaf node.
+fn hash_leaf(datum: &H256) -> H256 {
+    tagged_hash(tags::MERKLE_LEAF, datum.as_ref())
 }
 
 /// Given the hash of the left and right nodes, compute the hash of the parent node.
 fn hash_children(left: &H256, right: &H256) -> H256 {
     let new: Vec<u8> = [left.as_ref(), right.as_ref()].concat();
-    let dig = ring::digest::digest(&ring::digest::SHA256, new.as_slice());
-    H256::from(dig)
+    tagged_hash(tags::MERKLE_NODE, &new)
+}
+
+/// Hash the nodes of a level pairwise, the last node of an odd level with itself.
+fn parent_level(nodes: &[H256]) -> Vec<H256> {
+    nodes
+        .chunks(2)
+        .map(|pair| hash_children(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+        .collect()
 }
 
-/// Duplicate the last node in `nodes` to make its length even.
-fn duplicate_last_node(nodes: &mut Vec<Option<MerkleTreeNode>>) {
-    let add =  MerkleTreeNode { hash: nodes.last().unwrap().as_ref().unwrap().hash, left: None, right: None };
-    nodes.push(Some(add))
+/// Lengths of the levels of a tree with `leaf_count` leaves, from the leaves up to the root.
+fn level_lengths(leaf_count: usize) -> Vec<usize> {
+    let mut lengths = vec![leaf_count];
+    let mut len = leaf_count;
+    while len > 1 {
+        len = len.div_ceil(2);
+        lengths.push(len);
+    }
+    lengths
 }
 
 impl MerkleTree {
     pub fn new<T>(data: &[T]) -> Self where T: Hashable, {
-        if data.is_empty(){
-            let input = [0;32];
-            let val = super::hash::H256::from(input);
-            let n = MerkleTreeNode {
-                hash: val,
-                left: None,
-                right: None,
-            };
-            return MerkleTree {
-                root: n,
-                level_count: 0
-            };
+        if data.is_empty() {
+            return MerkleTree { levels: Vec::new() };
         }
-        // create the leaf nodes:
-        let mut curr_level: Vec<Option<MerkleTreeNode>> = Vec::new();
-        for item in data {
-            curr_level.push(Some(MerkleTreeNode { hash: item.hash(), left: None, right: None }));
+        let mut levels = vec![data.iter().map(|item| hash_leaf(&item.hash())).collect::<Vec<H256>>()];
+        while levels.last().unwrap().len() > 1 {
+            let next = parent_level(levels.last().unwrap());
+            levels.push(next);
         }
-        let mut level_count = 1;
+        MerkleTree { levels }
+    }
 
-        // create the upper levels of the tree:
-        while curr_level.len() > 1 {
-            // Whenever a level of the tree has odd number of nodes, duplicate the last node to make the number even:
-            if curr_level.len() % 2 == 1 {
-                duplicate_last_node(&mut curr_level); // implement this helper function
-            }
-            assert_eq!(curr_level.len() % 2, 0); // make sure we now have even number of nodes.
-
-            let mut next_level: Vec<Option<MerkleTreeNode>> = Vec::new();
-            for i in 0..curr_level.len() / 2 {
-                let left = curr_level[i * 2].take().unwrap();
-                let right = curr_level[i * 2 + 1].take().unwrap();
-                let hash = hash_children(&left.hash, &right.hash); // implement this helper function
-                next_level.push(Some(MerkleTreeNode { hash, left: Some(Box::new(left)), right: Some(Box::new(right)) }));
-            }
-            curr_level = next_level;
-            level_count += 1;
-        }
-        MerkleTree {
-            root: curr_level[0].take().unwrap(),
-            level_count,
+    /// The root, all zeros for an empty tree
+    pub fn root(&self) -> H256 {
+        match self.levels.last() {
+            Some(level) => level[0],
+            None => H256::from([0u8; 32]),
         }
     }
 
-    pub fn root(&self) -> H256 {
-        self.root.hash
+    pub fn leaf_count(&self) -> usize {
+        self.levels.first().map_or(0, |leaves| leaves.len())
     }
 
-    /// Returns the Merkle Proof of data at index i
-    pub fn proof(&self, index: usize) -> Vec<H256> {
-        let mut leaf_num =  2usize.pow(self.level_count as u32 - 1);
-        assert!(index <= leaf_num - 1);
+    /// Append a leaf, only rehashing the nodes on its path to the root.
+    pub fn push<T>(&mut self, datum: &T) where T: Hashable, {
+        if self.levels.is_empty() {
+            self.levels.push(vec![hash_leaf(&datum.hash())]);
+            return;
+        }
+        self.levels[0].push(hash_leaf(&datum.hash()));
+        let mut index = self.levels[0].len() - 1;
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            let nodes = &self.levels[level];
+            let left = nodes[index & !1];
+            let right = nodes.get(index | 1).copied().unwrap_or(left);
+            let parent = hash_children(&left, &right);
+            index /= 2;
+            if level + 1 == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            let up = &mut self.levels[level + 1];
+            if index < up.len() {
+                up[index] = parent;
+            } else {
+                up.push(parent);
+            }
+            level += 1;
+        }
+    }
 
+    /// Returns the Merkle Proof of data at index i, from the level below the root down to the
+    /// sibling of the leaf. Panics if there is no leaf at `index`.
+    pub fn proof(&self, index: usize) -> Vec<H256> {
+        assert!(index < self.leaf_count(), "no leaf at index {}", index);
         let mut merkle_proof: Vec<H256> = Vec::new();
-
         let mut idx = index;
-        let mut node = &self.root;
-        while leaf_num > 1 {
-            leaf_num /= 2;
-            if idx < leaf_num {
-                merkle_proof.push(node.right.as_ref().unwrap().hash);
-                node = &node.left.as_ref().unwrap();
-            } else {
-                merkle_proof.push(node.left.as_ref().unwrap().hash);
-                node = &node.right.as_ref().unwrap();
-                idx -= leaf_num;
-            }
+        for nodes in self.levels[..self.levels.len() - 1].iter() {
+            merkle_proof.push(*nodes.get(idx ^ 1).unwrap_or(&nodes[idx]));
+            idx /= 2;
         }
+        merkle_proof.reverse();
         merkle_proof
     }
+
+    /// Returns a single proof for the leaves at `indices`: the nodes needed to recompute the root
+    /// that cannot be computed from those leaves, level by level from the leaves up. Panics if
+    /// there is no leaf at one of the indices.
+    pub fn batch_proof(&self, indices: &[usize]) -> Vec<H256> {
+        let mut known: Vec<usize> = indices.to_vec();
+        known.sort_unstable();
+        known.dedup();
+        if let Some(&last) = known.last() {
+            assert!(last < self.leaf_count(), "no leaf at index {}", last);
+        }
+        let mut proof = Vec::new();
+        for nodes in self.levels[..self.levels.len().saturating_sub(1)].iter() {
+            for &idx in known.iter() {
+                let sibling = idx ^ 1;
+                if sibling < nodes.len() && known.binary_search(&sibling).is_err() {
+                    proof.push(nodes[sibling]);
+                }
+            }
+            known = known.iter().map(|idx| idx / 2).collect();
+            known.dedup();
+        }
+        proof
+    }
+}
+
+/// Verify a proof made by `MerkleTree::batch_proof` for `leaves`, pairs of leaf index and datum
+/// hash, in a tree of `leaf_size` leaves.
+pub fn verify_batch(root: &H256, leaves: &[(usize, H256)], proof: &[H256], leaf_size: usize) -> bool {
+    let mut known: Vec<(usize, H256)> = leaves.iter().map(|(idx, datum)| (*idx, hash_leaf(datum))).collect();
+    known.sort_by_key(|(idx, _)| *idx);
+    known.dedup_by_key(|(idx, _)| *idx);
+    if known.is_empty() || known.len() != leaves.len() || known.last().unwrap().0 >= leaf_size {
+        return false;
+    }
+    let mut proof = proof.iter();
+    for len in level_lengths(leaf_size) {
+        if len == 1 {
+            break;
+        }
+        let mut next = Vec::new();
+        let mut i = 0;
+        while i < known.len() {
+            let (idx, hash) = known[i];
+            let parent = if idx % 2 == 1 {
+                match proof.next() {
+                    Some(left) => hash_children(left, &hash),
+                    None => return false,
+                }
+            } else if i + 1 < known.len() && known[i + 1].0 == idx + 1 {
+                i += 1;
+                hash_children(&hash, &known[i].1)
+            } else if idx + 1 == len {
+                hash_children(&hash, &hash)
+            } else {
+                match proof.next() {
+                    Some(right) => hash_children(&hash, right),
+                    None => return false,
+                }
+            };
+            next.push((idx / 2, parent));
+            i += 1;
+        }
+        known = next;
+    }
+    proof.next().is_none() && known.len() == 1 && known[0].1 == *root
 }
 
 /// Verify that the datum hash with a vector of proofs will produce the Merkle root. Also need the
@@ -110,10 +181,11 @@ pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size
         proof_num += 1;
         leaf_num = (leaf_num+1)/2;
     }
-    assert_eq!(proof.len(), proof_num);
+    if proof.len() != proof_num || index >= leaf_size {
+        return false;
+    }
 
-    let raw_hash: [u8; 32] = datum.into();
-    let mut hash = H256::from(raw_hash);
+    let mut hash = hash_leaf(datum);
     let mut idx = index;
     for x in proof.iter().rev() {
         if idx % 2 == 0 {
@@ -148,14 +220,16 @@ mod tests {
         let root = merkle_tree.root();
         assert_eq!(
             root,
-            (hex!("6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920")).into()
+            (hex!("f41ce401ed01de9c9aadfb1079215712a4faea9860e738f133fd70cc1c59243d")).into()
         );
         // "b69566be6e1720872f73651d1851a0eae0060a132cf0f64a0ffaea248de6cba0" is the hash of
-        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d"
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
+        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d", and its leaf
+        // "28eb09a5bf2349f50fd347eaa19b2a683d89fe99ac934acef78301d7c94ab15d" is its hash tagged
+        // "bitcoin/merkle-leaf"
+        // "af7ac440f75281a6e7a8f103e208203c4ce2456d82428192079b435e2a371812" is the leaf of
         // "0101010101010101010101010101010101010101010101010101010101010202"
-        // "6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920" is the hash of
-        // the concatenation of these two hashes "b69..." and "965..."
+        // "f41ce401ed01de9c9aadfb1079215712a4faea9860e738f133fd70cc1c59243d" is the hash tagged
+        // "bitcoin/merkle-node" of the concatenation of the two leaves "28e..." and "af7..."
         // notice that the order of these two matters
     }
 
@@ -165,9 +239,9 @@ mod tests {
         let merkle_tree = MerkleTree::new(&input_data);
         let proof = merkle_tree.proof(0);
         assert_eq!(proof,
-                   vec![hex!("965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f").into()]
+                   vec![hex!("af7ac440f75281a6e7a8f103e208203c4ce2456d82428192079b435e2a371812").into()]
         );
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
+        // "af7ac440f75281a6e7a8f103e208203c4ce2456d82428192079b435e2a371812" is the leaf of
         // "0101010101010101010101010101010101010101010101010101010101010202"
     }
 
@@ -178,4 +252,71 @@ mod tests {
         let proof = merkle_tree.proof(0);
         assert!(verify(&merkle_tree.root(), &input_data[0].hash(), &proof, 0, input_data.len()));
     }
+
+    fn leaves(n: usize) -> Vec<H256> {
+        (0..n).map(|i| H256::from([i as u8; 32])).collect()
+    }
+
+    #[test]
+    fn proofs_for_any_leaf_count() {
+        for n in 1..=17 {
+            let data = leaves(n);
+            let tree = MerkleTree::new(&data);
+            for (i, datum) in data.iter().enumerate() {
+                let proof = tree.proof(i);
+                assert!(verify(&tree.root(), &datum.hash(), &proof, i, n), "leaf {} of {}", i, n);
+                assert!(!verify(&tree.root(), &H256::from([0xaa; 32]), &proof, i, n));
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree() {
+        let tree = MerkleTree::new::<H256>(&[]);
+        assert_eq!(tree.root(), H256::from([0u8; 32]));
+        assert_eq!(tree.leaf_count(), 0);
+        assert!(tree.batch_proof(&[]).is_empty());
+    }
+
+    #[test]
+    fn inner_node_is_not_a_leaf() {
+        let data = leaves(4);
+        let tree = MerkleTree::new(&data);
+        // the left child of the root, presented as a datum of a two-leaf tree
+        let inner = tree.levels[1][0];
+        let sibling = tree.levels[1][1];
+        assert!(!verify(&tree.root(), &inner, &[sibling], 0, 2));
+    }
+
+    #[test]
+    fn push_matches_new() {
+        let data = leaves(13);
+        let mut tree = MerkleTree::new::<H256>(&[]);
+        for (i, datum) in data.iter().enumerate() {
+            tree.push(datum);
+            assert_eq!(tree.root(), MerkleTree::new(&data[..=i]).root());
+        }
+    }
+
+    #[test]
+    fn batch_proofs() {
+        for n in 1..=11 {
+            let data = leaves(n);
+            let tree = MerkleTree::new(&data);
+            let subsets: Vec<Vec<usize>> = vec![vec![0], vec![n - 1], (0..n).collect(), (0..n).step_by(3).collect()];
+            for indices in subsets {
+                let proof = tree.batch_proof(&indices);
+                let proven: Vec<(usize, H256)> = indices.iter().map(|&i| (i, data[i].hash())).collect();
+                assert!(verify_batch(&tree.root(), &proven, &proof, n), "{:?} of {}", indices, n);
+                let mut wrong = proven.clone();
+                wrong[0].1 = H256::from([0xaa; 32]);
+                assert!(!verify_batch(&tree.root(), &wrong, &proof, n));
+            }
+        }
+        let data = leaves(8);
+        let tree = MerkleTree::new(&data);
+        // proving every leaf needs nothing else
+        assert!(tree.batch_proof(&(0..8).collect::<Vec<_>>()).is_empty());
+        assert_eq!(tree.batch_proof(&[0, 1]).len(), 2);
+    }
 }
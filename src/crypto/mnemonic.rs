@@ -0,0 +1,147 @@
+//! Mnemonic seed phrases as in BIP39, so that a wallet seed can be written down and typed back.
+use ring::digest;
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+const WORDLIST: &str = include_str!("bip39-english.txt");
+const PBKDF2_ROUNDS: u32 = 2048;
+
+fn words() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+/// A phrase of 12 to 24 words encoding 128 to 256 bits of entropy and a checksum.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+
+impl std::fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // keep seeds out of logs
+        write!(f, "Mnemonic({} words)", self.entropy.len() * 3 / 4)
+    }
+}
+
+impl Mnemonic {
+    /// A fresh phrase of `word_count` words: 12, 15, 18, 21 or 24.
+    pub fn generate(word_count: usize) -> Result<Self, String> {
+        let mut entropy = vec![0u8; word_count * 4 / 3];
+        SystemRandom::new().fill(&mut entropy).map_err(|_| "no randomness available".to_string())?;
+        Self::from_entropy(&entropy)
+    }
+
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, String> {
+        if entropy.len() < 16 || entropy.len() > 32 || !entropy.len().is_multiple_of(4) {
+            return Err(format!("invalid entropy length {}", entropy.len()));
+        }
+        Ok(Mnemonic { entropy: entropy.to_vec() })
+    }
+
+    /// Read a phrase, checking its words and checksum.
+    pub fn parse(phrase: &str) -> Result<Self, String> {
+        let list = words();
+        let indices = phrase.split_whitespace()
+            .map(|word| list.binary_search(&word).map_err(|_| format!("unknown word {}", word)))
+            .collect::<Result<Vec<usize>, String>>()?;
+        if ![12, 15, 18, 21, 24].contains(&indices.len()) {
+            return Err(format!("a phrase has 12 to 24 words, not {}", indices.len()));
+        }
+        let mut bits = Vec::with_capacity(indices.len() * 11);
+        for index in indices {
+            bits.extend((0..11).rev().map(|i| (index >> i) & 1 == 1));
+        }
+        let checksum_bits = bits.len() / 33;
+        let entropy: Vec<u8> = bits[..bits.len() - checksum_bits]
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |acc, bit| (acc << 1) | *bit as u8))
+            .collect();
+        let mnemonic = Self::from_entropy(&entropy)?;
+        if mnemonic.checksum() != bits[bits.len() - checksum_bits..] {
+            return Err("invalid mnemonic checksum".to_string());
+        }
+        Ok(mnemonic)
+    }
+
+    /// The words, separated by spaces.
+    pub fn phrase(&self) -> String {
+        let list = words();
+        let mut bits: Vec<bool> = self.entropy.iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+            .collect();
+        bits.extend(self.checksum());
+        bits.chunks(11)
+            .map(|chunk| list[chunk.iter().fold(0usize, |acc, bit| (acc << 1) | *bit as usize)])
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// The 64-byte seed of the phrase, stretched with an optional passphrase.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let mut seed = [0u8; 64];
+        let salt = format!("mnemonic{}", passphrase);
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA512,
+            NonZeroU32::new(PBKDF2_ROUNDS).unwrap(),
+            salt.as_bytes(),
+            self.phrase().as_bytes(),
+            &mut seed,
+        );
+        seed
+    }
+
+    /// First `entropy bits / 32` bits of the SHA256 of the entropy
+    fn checksum(&self) -> Vec<bool> {
+        let hash = digest::digest(&digest::SHA256, &self.entropy);
+        (0..self.entropy.len() / 4)
+            .map(|i| (hash.as_ref()[i / 8] >> (7 - i % 8)) & 1 == 1)
+            .collect()
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wordlist() {
+        let list = words();
+        assert_eq!(list.len(), 2048);
+        assert!(list.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn bip39_vectors() {
+        for (entropy, phrase) in [
+            ("00000000000000000000000000000000",
+             "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"),
+            ("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+             "legal winner thank year wave sausage worth useful legal winner thank yellow"),
+            ("9e885d952ad362caeb4efe34a8e91bd2",
+             "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic"),
+            ("6610b25967cdcca9d59875f5cb50b0ea75433311869e930b",
+             "gravity machine north sort system female filter attitude volume fold club stay feature office ecology stable narrow fog"),
+            ("68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+             "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length"),
+        ] {
+            let mnemonic = Mnemonic::from_entropy(&hex::decode(entropy).unwrap()).unwrap();
+            assert_eq!(mnemonic.phrase(), phrase);
+            assert_eq!(Mnemonic::parse(phrase).unwrap(), mnemonic);
+        }
+        let seed = Mnemonic::from_entropy(&[0u8; 16]).unwrap().to_seed("TREZOR");
+        assert_eq!(hex::encode(&seed[..]), concat!(
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553",
+            "1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"));
+    }
+
+    #[test]
+    fn rejects_bad_phrases() {
+        let mnemonic = Mnemonic::generate(24).unwrap();
+        assert_eq!(Mnemonic::parse(&mnemonic.phrase()).unwrap(), mnemonic);
+        assert!(Mnemonic::parse("abandon abandon abandon").is_err());
+        assert!(Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon").is_err());
+        assert!(Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon bitcoin").is_err());
+        assert!(Mnemonic::generate(13).is_err());
+    }
+}
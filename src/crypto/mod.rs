@@ -1,3 +1,7 @@
 pub mod hash;
 pub mod merkle;
 pub mod key_pair;
+pub mod batch;
+pub mod sigcache;
+pub mod hd;
+pub mod mnemonic;
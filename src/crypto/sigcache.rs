@@ -0,0 +1,177 @@
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use ring::digest;
+use crate::crypto::batch::{verify_parallel, SignatureCheck, SignatureVerifier};
+use crate::crypto::hash::H256;
+
+/// Entries kept by default, about 3 MB
+pub const DEFAULT_SIGNATURE_CACHE_SIZE: usize = 50_000;
+
+/// Signatures found valid, keyed by `(txid, public key, signature hash)`. The txid is the hash
+/// of the whole signed transaction, witnesses included, so it commits to the signature bytes
+/// and a different signature of the same message is a different transaction. Invalid
+/// signatures are not remembered; when full, the oldest entry makes room for the new one.
+/// Shared by the worker threads, so that a transaction verified at mempool admission is not
+/// verified again when its block arrives.
+#[derive(Debug)]
+pub struct SignatureCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    keys: HashSet<H256>,
+    /// Insertion order, for eviction
+    order: VecDeque<H256>,
+}
+
+/// Snapshot of the cache reported by the `/sigcache/status` endpoint.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignatureCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+fn key(check: &SignatureCheck) -> H256 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(check.txid.as_ref());
+    ctx.update(&(check.public_key.len() as u32).to_le_bytes());
+    ctx.update(&check.public_key);
+    ctx.update(check.message.as_ref());
+    ctx.finish().into()
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIGNATURE_CACHE_SIZE)
+    }
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        SignatureCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the signature is known to be valid, counting a hit or a miss.
+    pub fn contains(&self, check: &SignatureCheck) -> bool {
+        let found = self.entries.lock().unwrap().keys.contains(&key(check));
+        self.count(found);
+        found
+    }
+
+    /// Verify the signatures not in the cache, in parallel, and remember the valid ones.
+    /// Returns whether each of `checks` is valid.
+    pub fn verify_batch(&self, checks: &[SignatureCheck]) -> Vec<bool> {
+        let keys: Vec<H256> = checks.iter().map(key).collect();
+        let known: Vec<bool> = {
+            let entries = self.entries.lock().unwrap();
+            keys.iter().map(|key| entries.keys.contains(key)).collect()
+        };
+        for found in known.iter() {
+            self.count(*found);
+        }
+        let unknown: Vec<SignatureCheck> = checks.iter().zip(known.iter())
+            .filter(|(_, known)| !**known)
+            .map(|(check, _)| check.clone())
+            .collect();
+        let mut verified = verify_parallel(&unknown).into_iter();
+
+        let mut entries = self.entries.lock().unwrap();
+        keys.into_iter().zip(known)
+            .map(|(key, known)| {
+                if known {
+                    return true;
+                }
+                let ok = verified.next().unwrap_or(false);
+                if ok {
+                    self.insert(&mut entries, key);
+                }
+                ok
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> SignatureCacheStats {
+        SignatureCacheStats {
+            entries: self.entries.lock().unwrap().keys.len(),
+            capacity: self.capacity,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn count(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn insert(&self, entries: &mut Entries, key: H256) {
+        if self.capacity == 0 || !entries.keys.insert(key) {
+            return;
+        }
+        entries.order.push_back(key);
+        while entries.order.len() > self.capacity {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.keys.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl SignatureVerifier for SignatureCache {
+    fn verify(&self, check: &SignatureCheck) -> bool {
+        if self.contains(check) {
+            return true;
+        }
+        let ok = check.verify();
+        if ok {
+            self.insert(&mut self.entries.lock().unwrap(), key(check));
+        }
+        ok
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::crypto::batch::generate_random_checks;
+
+    #[test]
+    fn remembers_valid_signatures() {
+        let mut checks = generate_random_checks(4);
+        checks[1].signature[0] ^= 1;
+        let cache = SignatureCache::default();
+        assert_eq!(cache.verify_batch(&checks), vec![true, false, true, true]);
+        assert_eq!(cache.stats(), SignatureCacheStats { entries: 3, capacity: DEFAULT_SIGNATURE_CACHE_SIZE, hits: 0, misses: 4 });
+        assert!(cache.verify(&checks[3]));
+        assert!(!cache.verify(&checks[1]));
+        assert_eq!(cache.verify_batch(&checks), vec![true, false, true, true]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4, 6));
+    }
+
+    #[test]
+    fn evicts_oldest() {
+        let checks = generate_random_checks(5);
+        let cache = SignatureCache::new(3);
+        for check in checks.iter() {
+            assert!(cache.verify(check));
+        }
+        assert_eq!(cache.stats().entries, 3);
+        assert!(!cache.contains(&checks[0]));
+        assert!(!cache.contains(&checks[1]));
+        assert!(cache.contains(&checks[4]));
+    }
+}
@@ -0,0 +1,282 @@
+//! Consensus encoding of the objects that get hashed or signed. Unlike bincode, the layout is
+//! specified here field by field and does not depend on serde attributes:
+//!
+//! * integers are little endian, with their natural width (`u8`, `u32`, `u128`)
+//! * hashes are their raw bytes
+//! * sequences and byte strings are prefixed with their length as a varint: one byte below 0xfd,
+//!   else 0xfd, 0xfe or 0xff followed by a 2, 4 or 8 byte little endian length
+//! * a top-level object starts with `ENCODING_VERSION`
+use crate::crypto::hash::{H160, H256};
+
+/// Version byte of the encoding, to be bumped on any layout change
+pub const ENCODING_VERSION: u8 = 1;
+
+pub trait Encode {
+    /// Append the encoding of the object, without version byte.
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    /// The versioned encoding of the object.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![ENCODING_VERSION];
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+pub trait Decode: Sized {
+    /// Read the object written by `Encode::encode_to`.
+    fn decode_from(reader: &mut Reader) -> Result<Self, String>;
+
+    /// Read a versioned encoding, which must be consumed entirely.
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+        let version = u8::decode_from(&mut reader)?;
+        if version != ENCODING_VERSION {
+            return Err(format!("unknown encoding version {}", version));
+        }
+        let value = Self::decode_from(&mut reader)?;
+        if !reader.is_empty() {
+            return Err("trailing bytes after encoded object".to_string());
+        }
+        Ok(value)
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("unexpected end of encoded data".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+/// Append a varint.
+pub fn encode_varint(value: u64, out: &mut Vec<u8>) {
+    if value < 0xfd {
+        out.push(value as u8);
+    } else if value <= 0xffff {
+        out.push(0xfd);
+        out.extend_from_slice(&(value as u16).to_le_bytes());
+    } else if value <= 0xffff_ffff {
+        out.push(0xfe);
+        out.extend_from_slice(&(value as u32).to_le_bytes());
+    } else {
+        out.push(0xff);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Read a varint, rejecting non-minimal encodings so that every value has one encoding only.
+pub fn decode_varint(reader: &mut Reader) -> Result<u64, String> {
+    let (value, min) = match u8::decode_from(reader)? {
+        0xfd => (u16::from_le_bytes(array(reader.take(2)?)) as u64, 0xfd),
+        0xfe => (u32::from_le_bytes(array(reader.take(4)?)) as u64, 0x1_0000),
+        0xff => (u64::from_le_bytes(array(reader.take(8)?)), 0x1_0000_0000),
+        small => return Ok(small as u64),
+    };
+    if value < min {
+        return Err("non-minimal varint".to_string());
+    }
+    Ok(value)
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut array = [0u8; N];
+    array.copy_from_slice(bytes);
+    array
+}
+
+macro_rules! impl_int {
+    ( $t:ty ) => {
+        impl Encode for $t {
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $t {
+            fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+                Ok(<$t>::from_le_bytes(array(reader.take(std::mem::size_of::<$t>())?)))
+            }
+        }
+    };
+}
+
+impl_int!(u8);
+impl_int!(u16);
+impl_int!(u32);
+impl_int!(u64);
+impl_int!(u128);
+
+impl Encode for H256 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+}
+
+impl Decode for H256 {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        Ok(H256::from(array::<32>(reader.take(32)?)))
+    }
+}
+
+impl Encode for H160 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+}
+
+impl Decode for H160 {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        Ok(H160::from(array::<20>(reader.take(20)?)))
+    }
+}
+
+/// Byte strings and sequences of objects alike: a varint length, then the elements
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        encode_varint(self.len() as u64, out);
+        for item in self.iter() {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let len = decode_varint(reader)?;
+        // every element takes at least one byte, which bounds the allocation
+        if len > reader.bytes.len() as u64 {
+            return Err("sequence longer than the encoded data".to_string());
+        }
+        (0..len).map(|_| T::decode_from(reader)).collect()
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::block::{Block, Header};
+    use crate::crypto::hash::Hashable;
+    use crate::signedtrans::SignedTrans;
+    use crate::condition::{Condition, SigHashType, WitnessSignature};
+    use crate::transaction::{Input, Output, Transaction};
+
+    fn sample_transaction() -> SignedTrans {
+        SignedTrans {
+            transaction: Transaction {
+                id: [0x11u8; 32].into(),
+                inputs: vec![Input {
+                    index: 3,
+                    previous_hash: [0x22u8; 32].into(),
+                    witness: vec![WitnessSignature {
+                        public_key: vec![0xdd],
+                        signature: vec![0xee, 0xff],
+                        sighash_type: SigHashType::SINGLE.anyone_can_pay(),
+                    }],
+                }],
+                outputs: vec![Output {
+                    balance: 7,
+                    address: [0x33u8; 20].into(),
+                    condition: Condition::RelativeTimelock {
+                        blocks: 6,
+                        condition: Box::new(Condition::Multisig { threshold: 1, keys: vec![vec![0x77]] }),
+                    },
+                }],
+            },
+            signature: vec![0xaa, 0xbb],
+            public_key: vec![0xcc],
+        }
+    }
+
+    #[test]
+    fn varints() {
+        for (value, expected) in [
+            (0u64, "00"),
+            (0xfc, "fc"),
+            (0xfd, "fdfd00"),
+            (0xffff, "fdffff"),
+            (0x1_0000, "fe00000100"),
+            (0x1_0000_0000, "ff0000000001000000"),
+        ] {
+            let mut out = Vec::new();
+            encode_varint(value, &mut out);
+            assert_eq!(hex::encode(&out), expected);
+            assert_eq!(decode_varint(&mut Reader::new(&out)).unwrap(), value);
+        }
+        assert!(decode_varint(&mut Reader::new(&hex::decode("fd1000").unwrap())).is_err());
+    }
+
+    /// Encoding of `sample_transaction`, without the version byte
+    const SAMPLE_TRANSACTION: &str = concat!(
+        "1111111111111111111111111111111111111111111111111111111111111111", // id
+        "01", "2222222222222222222222222222222222222222222222222222222222222222", "03", // input
+        "01", "01dd", "02eeff", "83",                                       // witness
+        "01", "3333333333333333333333333333333333333333", "07",             // output
+        "03", "06000000", "01", "01", "01", "0177",                         // condition
+        "02aabb", "01cc",
+    );
+
+    #[test]
+    fn transaction_golden_vector() {
+        let tx = sample_transaction();
+        assert_eq!(hex::encode(tx.encode()), format!("01{}", SAMPLE_TRANSACTION));
+        let decoded = SignedTrans::decode(&tx.encode()).unwrap();
+        assert_eq!(decoded.hash(), tx.hash());
+    }
+
+    #[test]
+    fn block_golden_vector() {
+        let block = Block::builder()
+            .height(9)
+            .parent([0x44u8; 32].into())
+            .nonce(5)
+            .difficulty([0x55u8; 32].into())
+            .timestamp(0x0102)
+            .merkle_root([0x66u8; 32].into())
+            .transactions(vec![sample_transaction()])
+            .build();
+        let header = concat!(
+            "00000020",                                                         // version
+            "09000000",                                                         // height
+            "4444444444444444444444444444444444444444444444444444444444444444", // parent
+            "05000000",                                                         // nonce
+            "5555555555555555555555555555555555555555555555555555555555555555", // difficulty
+            "02010000000000000000000000000000",                                 // timestamp
+            "6666666666666666666666666666666666666666666666666666666666666666", // merkle root
+        );
+        assert_eq!(hex::encode(block.header.encode()), format!("01{}", header));
+        let encoded = block.encode();
+        // the header, then the transactions, which the block's version byte covers
+        assert_eq!(hex::encode(&encoded), format!("01{}01{}", header, SAMPLE_TRANSACTION));
+        let decoded = Block::decode(&encoded).unwrap();
+        assert_eq!(decoded.hash(), block.hash());
+        assert_eq!(decoded.content[0].hash(), block.content[0].hash());
+        assert_eq!(Header::decode(&block.header.encode()).unwrap().hash(), block.hash());
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        let tx = sample_transaction().encode();
+        assert!(SignedTrans::decode(&tx[..tx.len() - 1]).is_err());
+        assert!(SignedTrans::decode(&[tx.clone(), vec![0]].concat()).is_err());
+        let mut other_version = tx;
+        other_version[0] = 2;
+        assert!(SignedTrans::decode(&other_version).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Notifications of changes to the chain and the mempool. `Blockchain` and `Mempool` publish to
+//! an `EventBus`; the components reacting to those changes subscribe to the kinds of events
+//! they need: the relay announcing blocks and transactions to peers, the miner restarting on a
+//! new template, the metrics, and the API server streaming them to its `/events` clients.
+use crossbeam::channel::{self, Receiver, Sender, TrySendError};
+use serde_json::json;
+use std::sync::Mutex;
+use crate::crypto::hash::H256;
+
+/// Events an API client may fall behind by before it is dropped
+pub const CLIENT_BACKLOG: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    BlockConnected,
+    BlockDisconnected,
+    TxAddedToMempool,
+    TxRemoved,
+    Reorg,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 5] = [
+        EventKind::BlockConnected,
+        EventKind::BlockDisconnected,
+        EventKind::TxAddedToMempool,
+        EventKind::TxRemoved,
+        EventKind::Reorg,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::BlockConnected => "block_connected",
+            EventKind::BlockDisconnected => "block_disconnected",
+            EventKind::TxAddedToMempool => "tx_added_to_mempool",
+            EventKind::TxRemoved => "tx_removed",
+            EventKind::Reorg => "reorg",
+        }
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventKind::ALL.iter()
+            .find(|kind| kind.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("unknown event type {}", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A block joined the longest chain
+    BlockConnected { hash: H256, height: u32 },
+    /// A block left the longest chain in a reorg
+    BlockDisconnected { hash: H256, height: u32 },
+    TxAddedToMempool { txid: H256 },
+    /// A transaction left the mempool, as it was included in a block
+    TxRemoved { txid: H256 },
+    /// The longest chain switched branches, after the disconnections and connections it caused
+    Reorg { old_tip: H256, new_tip: H256, fork_point: H256, disconnected: usize, connected: usize },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::BlockConnected { .. } => EventKind::BlockConnected,
+            Event::BlockDisconnected { .. } => EventKind::BlockDisconnected,
+            Event::TxAddedToMempool { .. } => EventKind::TxAddedToMempool,
+            Event::TxRemoved { .. } => EventKind::TxRemoved,
+            Event::Reorg { .. } => EventKind::Reorg,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    /// The fields of the event, with hashes in hex
+    pub fn data(&self) -> serde_json::Value {
+        match self {
+            Event::BlockConnected { hash, height } | Event::BlockDisconnected { hash, height } => {
+                json!({ "hash": hash.to_string(), "height": height })
+            }
+            Event::TxAddedToMempool { txid } | Event::TxRemoved { txid } => json!({ "txid": txid.to_string() }),
+            Event::Reorg { old_tip, new_tip, fork_point, disconnected, connected } => json!({
+                "old_tip": old_tip.to_string(),
+                "new_tip": new_tip.to_string(),
+                "fork_point": fork_point.to_string(),
+                "disconnected": disconnected,
+                "connected": connected,
+            }),
+        }
+    }
+}
+
+/// Passes the events published to the subscribers of their kind. Publishing never blocks:
+/// the components of the node get every event, and a client that stops reading is dropped once
+/// its backlog is full.
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    kinds: Vec<EventKind>,
+    sender: Sender<Event>,
+}
+
+impl EventBus {
+    /// Receive the events of `kinds` published from now on, until the bus is closed.
+    pub fn subscribe(&self, kinds: &[EventKind]) -> Receiver<Event> {
+        self.add_subscriber(kinds, channel::unbounded())
+    }
+
+    /// Like `subscribe`, but dropped once `backlog` events behind, for clients that may stop
+    /// reading.
+    pub fn subscribe_bounded(&self, kinds: &[EventKind], backlog: usize) -> Receiver<Event> {
+        self.add_subscriber(kinds, channel::bounded(backlog))
+    }
+
+    pub fn publish(&self, event: Event) {
+        let kind = event.kind();
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            if !subscriber.kinds.contains(&kind) {
+                return true;
+            }
+            match subscriber.sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    /// Drop every subscriber, whose receivers end once they have read the events left.
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+
+    fn add_subscriber(&self, kinds: &[EventKind], (sender, receiver): (Sender<Event>, Receiver<Event>)) -> Receiver<Event> {
+        self.subscribers.lock().unwrap().push(Subscriber { kinds: kinds.to_vec(), sender });
+        receiver
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_by_kind() {
+        let bus = EventBus::default();
+        let everything = bus.subscribe(&EventKind::ALL);
+        let blocks = bus.subscribe(&[EventKind::BlockConnected]);
+        let tx = Event::TxAddedToMempool { txid: [1u8; 32].into() };
+        let block = Event::BlockConnected { hash: [2u8; 32].into(), height: 1 };
+        bus.publish(tx.clone());
+        bus.publish(block.clone());
+        assert_eq!(everything.try_iter().collect::<Vec<_>>(), vec![tx.clone(), block.clone()]);
+        assert_eq!(blocks.try_iter().collect::<Vec<_>>(), vec![block]);
+        assert_eq!(tx.data()["txid"], "01".repeat(32));
+        assert_eq!("tx_removed".parse::<EventKind>().unwrap(), EventKind::TxRemoved);
+        assert!("tx".parse::<EventKind>().is_err());
+
+        // closing ends the receivers once drained
+        bus.publish(tx.clone());
+        bus.close();
+        assert_eq!(everything.recv().unwrap(), tx);
+        assert!(everything.recv().is_err());
+    }
+
+    #[test]
+    fn drops_lagging_clients() {
+        let bus = EventBus::default();
+        let client = bus.subscribe_bounded(&EventKind::ALL, CLIENT_BACKLOG);
+        let node = bus.subscribe(&EventKind::ALL);
+        let gone = bus.subscribe(&EventKind::ALL);
+        drop(gone);
+        let event = Event::TxAddedToMempool { txid: [1u8; 32].into() };
+        for _ in 0..CLIENT_BACKLOG {
+            bus.publish(event.clone());
+        }
+        assert_eq!(bus.subscriber_count(), 2);
+        // the backlog of the client is now full, so one more drops it, but not the node
+        bus.publish(event);
+        assert_eq!(bus.subscriber_count(), 1);
+        assert_eq!(client.try_iter().count(), CLIENT_BACKLOG);
+        assert_eq!(node.try_iter().count(), CLIENT_BACKLOG + 1);
+    }
+}
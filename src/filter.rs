@@ -0,0 +1,212 @@
+use serde::{Serialize, Deserialize};
+use ring::digest;
+use crate::block::Block;
+use crate::crypto::hash::{H160, H256, Hashable};
+
+/// Golomb-Rice parameter: remainders take P bits
+const P: u8 = 19;
+/// Inverse false positive rate of a single item
+const M: u64 = 784_931;
+
+/// Compact filter of a block: a Golomb-coded set over the addresses its outputs pay to and the
+/// transactions its inputs spend. A client can test its own addresses against it without
+/// downloading the block, with a false positive rate of about 1/M.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockFilter {
+    pub block: H256,
+    /// Number of items in the set
+    pub n: u32,
+    pub data: Vec<u8>,
+}
+
+/// Filter item of an address paid by an output.
+pub fn address_item(address: &H160) -> Vec<u8> {
+    address.as_ref().to_vec()
+}
+
+/// Filter item of a spent outpoint. Inputs spend whole transactions in this ledger, so the
+/// outpoint is the id of the spent transaction.
+pub fn outpoint_item(previous_hash: &H256) -> Vec<u8> {
+    previous_hash.as_ref().to_vec()
+}
+
+impl BlockFilter {
+    /// Compute the filter of a block.
+    pub fn new(block: &Block) -> Self {
+        let mut items: Vec<Vec<u8>> = Vec::new();
+        for tx in block.content.iter() {
+            for output in tx.transaction.outputs.iter() {
+                items.push(address_item(&output.address));
+            }
+            for input in tx.transaction.inputs.iter() {
+                items.push(outpoint_item(&input.previous_hash));
+            }
+        }
+        items.sort();
+        items.dedup();
+        let block_hash = block.hash();
+        let n = items.len() as u32;
+        let mut values: Vec<u64> = items.iter().map(|item| hash_to_range(&block_hash, item, n)).collect();
+        values.sort_unstable();
+
+        let mut writer = BitWriter::default();
+        let mut last = 0;
+        for value in values {
+            let delta = value - last;
+            last = value;
+            writer.write_unary(delta >> P);
+            writer.write_bits(delta, P);
+        }
+        BlockFilter { block: block_hash, n, data: writer.finish() }
+    }
+
+    /// Whether any of `items` may be in the block. False positives are possible, false
+    /// negatives are not.
+    pub fn match_any(&self, items: &[Vec<u8>]) -> bool {
+        if self.n == 0 || items.is_empty() {
+            return false;
+        }
+        let mut targets: Vec<u64> = items.iter().map(|item| hash_to_range(&self.block, item, self.n)).collect();
+        targets.sort_unstable();
+
+        let mut reader = BitReader::new(&self.data);
+        let mut value = 0;
+        let mut targets = targets.into_iter().peekable();
+        for _ in 0..self.n {
+            let delta = match (reader.read_unary(), reader.read_bits(P)) {
+                (Some(quotient), Some(remainder)) => (quotient << P) | remainder,
+                _ => return false,
+            };
+            value += delta;
+            while let Some(&target) = targets.peek() {
+                if target == value {
+                    return true;
+                }
+                if target > value {
+                    break;
+                }
+                targets.next();
+            }
+            if targets.peek().is_none() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+/// Map an item uniformly into [0, n * M), keyed by the block hash so that false positives differ
+/// from block to block.
+fn hash_to_range(key: &H256, item: &[u8], n: u32) -> u64 {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(key.as_ref());
+    ctx.update(item);
+    let digest = ctx.finish();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest.as_ref()[..8]);
+    let hash = u64::from_be_bytes(bytes);
+    ((hash as u128 * (n as u64 * M) as u128) >> 64) as u64
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    used: u8, // bits used in the last byte
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.used == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+        }
+        self.used = (self.used + 1) % 8;
+    }
+
+    fn write_unary(&mut self, value: u64) {
+        for _ in 0..value {
+            self.write_bit(true);
+        }
+        self.write_bit(false);
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize, // in bits
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.position / 8)?;
+        let bit = byte & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn read_unary(&mut self) -> Option<u64> {
+        let mut value = 0;
+        while self.read_bit()? {
+            value += 1;
+        }
+        Some(value)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::block::generate_random_block;
+    use crate::crypto::hash::generate_rand_hash256;
+
+    #[test]
+    fn matches_block_items() {
+        let block = generate_random_block(&generate_rand_hash256());
+        let filter = BlockFilter::new(&block);
+        assert!(filter.n > 0);
+        for tx in block.content.iter() {
+            for output in tx.transaction.outputs.iter() {
+                assert!(filter.match_any(&[address_item(&output.address)]));
+            }
+            for input in tx.transaction.inputs.iter() {
+                assert!(filter.match_any(&[outpoint_item(&input.previous_hash)]));
+            }
+        }
+        let strangers: Vec<Vec<u8>> = (0..100u8).map(|i| address_item(&[i; 20].into())).collect();
+        assert!(!filter.match_any(&strangers));
+    }
+
+    #[test]
+    fn empty_block_matches_nothing() {
+        let mut block = generate_random_block(&generate_rand_hash256());
+        block.content.clear();
+        let filter = BlockFilter::new(&block);
+        assert_eq!(filter.n, 0);
+        assert!(filter.data.is_empty());
+        assert!(!filter.match_any(&[address_item(&[1u8; 20].into())]));
+    }
+}
@@ -18,7 +18,9 @@ use rand::Rng;
 use ring::signature::KeyPair;
 use crate::crypto::hash::{generate_rand_hash256, H160, H256, Hashable};
 use crate::crypto::key_pair;
-use crate::transaction::{Input, Output, sign, Transaction};
+use crate::transaction::{Input, Output, sign, sign_input, Transaction};
+use crate::address::Address;
+use crate::condition::{Condition, SigHashType};
 
 enum ControlSignal {
     Start(u64), // the number controls the lambda of interval between block generation
@@ -72,7 +74,9 @@ pub fn new(
 
 impl Generator {
     pub fn exit(&self) {
-        self.control_chan.send(ControlSignal::Exit).unwrap();
+        if self.control_chan.send(ControlSignal::Exit).is_err() {
+            info!("Generator already shut down");
+        }
     }
 
     pub fn start(&self, lambda: u64) {
@@ -84,14 +88,15 @@ impl Generator {
 }
 
 impl Context {
-    pub fn start(mut self) {
-        thread::Builder::new()
-            .name("miner".to_string())
+    pub fn start(mut self) -> thread::JoinHandle<()> {
+        let handle = thread::Builder::new()
+            .name("generator".to_string())
             .spawn(move || {
                 self.generator_loop();
             })
             .unwrap();
         info!("Generator initialized into paused mode");
+        handle
     }
 
     fn handle_control_signal(&mut self, signal: ControlSignal) {
@@ -143,13 +148,18 @@ impl Context {
                     let public_key = key.public_key();
                     let byte_pbkey = public_key.as_ref();
                     let address = H160::hash(&byte_pbkey);
-                    println!("generate address: {:?}",address);
+                    let network = self.bc.lock().unwrap().params().network;
+                    println!("generate address: {}", Address::new(network, address));
                     let mut address_vec = vec![address];
                     self.bc.lock().unwrap().address_list.push(address);
                     self.server.broadcast(Message::Address(address_vec));
                     key_map.insert(address, key);
                 }
-                println!("all addresses: {:?}", self.bc.lock().unwrap().address_list);
+                let bc = self.bc.lock().unwrap();
+                let network = bc.params().network;
+                let shown: Vec<String> = bc.address_list.iter().map(|a| Address::new(network, *a).to_string()).collect();
+                println!("all addresses: {:?}", shown);
+                drop(bc);
 
                 // init money
                 let mut bc = self.bc.lock().unwrap();
@@ -158,6 +168,7 @@ impl Context {
                     init.push(Output{
                         balance:0,
                         address:addr,
+                        condition: Condition::PayToAddress,
                     });
                     let trans = Transaction{id: generate_rand_hash256(), inputs: vec![], outputs: init};
                     let key = key_pair::random();
@@ -198,19 +209,24 @@ impl Context {
                     skip -= 1;
                 }
             }
-            let inputs = Input{index: 1, previous_hash:from_tx};
+            let spent = bc.spent_outputs(&from_tx).map(|(outputs, _)| outputs).unwrap_or_default();
+            let value = spent.iter().fold(0u8, |sum, out| sum.wrapping_add(out.balance));
+            let inputs = Input{index: value, previous_hash:from_tx, witness: Vec::new()};
 
             let mut val:u8 = rng.gen();
             val %= bc.address_list.len() as u8;
             let dest_address = bc.address_list[val as usize];
-            let outputs = Output{ balance: 1, address:dest_address};
+            let outputs = Output{ balance: 1, address:dest_address, condition: Condition::PayToAddress};
 
             let id = generate_rand_hash256();
-            let trans = Transaction{id, inputs:vec![inputs], outputs:vec![outputs] };
+            let mut trans = Transaction{id, inputs:vec![inputs], outputs:vec![outputs] };
 
             // generate signature
             let s = sign(&trans, &from_key);
             let p = from_key.public_key().as_ref().to_vec();
+            if let Ok(witness) = sign_input(&trans, 0, &spent, from_key, SigHashType::ALL) {
+                trans.inputs[0].witness.push(witness);
+            }
 
             // generate trans using state (may be invalid)
             let trans = SignedTrans{
@@ -229,10 +245,6 @@ impl Context {
             bc.update_state(&trans.clone(), self.mp.lock().unwrap().clone().pool.len());
             drop(bc);
 
-            // broadcast
-            let msg = Message::NewTransactionHashes(vec![trans.hash()]);
-            self.server.broadcast(msg);
-
             if let OperatingState::Run(i) = self.operating_state {
                 if i != 0 {
                     let interval = time::Duration::from_micros(i as u64);
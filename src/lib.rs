@@ -0,0 +1,29 @@
+//! The node, its wallet and consensus code, shared by the `bitcoin` node binary and the
+//! `bitcoin-cli` client.
+#[cfg(test)]
+#[macro_use]
+extern crate hex_literal;
+
+pub mod api;
+pub mod block;
+pub mod blockchain;
+pub mod crypto;
+pub mod miner;
+pub mod network;
+pub mod transaction;
+pub mod signedtrans;
+pub mod mempool;
+pub mod orphan;
+pub mod events;
+pub mod metrics;
+pub mod state;
+pub mod generator;
+pub mod params;
+pub mod config;
+pub mod spv;
+pub mod filter;
+pub mod encoding;
+pub mod clock;
+pub mod condition;
+pub mod wallet;
+pub mod address;
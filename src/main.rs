@@ -1,104 +1,205 @@
-#[cfg(test)]
-#[macro_use]
-extern crate hex_literal;
-
-pub mod api;
-pub mod block;
-pub mod blockchain;
-pub mod crypto;
-pub mod miner;
-pub mod network;
-pub mod transaction;
-mod signedtrans;
-mod mempool;
-mod state;
-mod generator;
-
 use clap::clap_app;
 use crossbeam::channel;
 use log::{error, info};
-use api::Server as ApiServer;
-use network::{server, worker};
+use bitcoin::api::{Context as ApiContext, Server as ApiServer};
+use bitcoin::network::{relay, server, worker};
+use bitcoin::{generator, miner};
 use std::net;
 use std::process;
 use std::thread;
 use std::time;
 use std::sync::{Arc, Mutex};
-use crate::blockchain::Blockchain;
-use crate::mempool::Mempool;
+use bitcoin::blockchain::Blockchain;
+use bitcoin::mempool::Mempool;
+use bitcoin::orphan::OrphanPool;
+use bitcoin::events::EventBus;
+use bitcoin::metrics::Metrics;
+use bitcoin::network::message::Handshake;
+use bitcoin::params::{ChainParams, Network};
+use bitcoin::config::Config;
+use bitcoin::crypto::mnemonic::Mnemonic;
+use bitcoin::wallet::{HdWallet, Keystore, MNEMONIC_WORDS};
+use bitcoin::address::Address;
+use bitcoin::crypto::sigcache::SignatureCache;
+use bitcoin::miner::MiningLimit;
+use std::path::Path;
 
 fn main() {
     // parse command line arguments
     let matches = clap_app!(Bitcoin =>
      (version: "0.1")
      (about: "Bitcoin client")
+     (@arg config: --config [FILE] "Reads the configuration from a TOML file; flags override its values")
+     (@arg dump_config: --("dump-config") "Prints the effective configuration and exits")
      (@arg verbose: -v ... "Increases the verbosity of logging")
-     (@arg peer_addr: --p2p [ADDR] default_value("127.0.0.1:6000") "Sets the IP address and the port of the P2P server")
-     (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
+     (@arg network: --network [NETWORK] possible_value[mainnet testnet regtest] "Sets the chain to run on")
+     (@arg peer_addr: --p2p [ADDR] "Sets the IP address and the port of the P2P server (default port depends on the network)")
+     (@arg api_addr: --api [ADDR] "Sets the IP address and the port of the API server (default port depends on the network)")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
-     (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
+     (@arg p2p_workers: --("p2p-workers") [INT] "Sets the number of worker threads for P2P server")
+     (@arg regtest: --regtest "Shorthand for --network regtest")
+     (@arg data_dir: --("data-dir") [DIR] "Sets the directory the node keeps its data in")
+     (@arg light: --light "Runs as a light client that only syncs headers and the watched addresses' transactions")
+     (@arg watch: --watch ... [ADDR] "Adds an address for the light client to track")
+     (@arg mine: --mine "Starts the miner at startup")
+     (@arg miner_lambda: --("miner-lambda") [INT] "Sets the lambda of the miner started at startup")
+     (@arg payout_address: --("payout-address") [ADDR] "Sets the address mined coinbases pay to")
+     (@arg generate: --generate "Starts the transaction generator at startup")
+     (@arg generator_lambda: --("generator-lambda") [INT] "Sets the lambda of the generator started at startup")
+     (@arg mempool_max_size: --("mempool-max-size") [INT] "Sets the maximum number of transactions in the mempool")
     )
     .get_matches();
 
-    // init logger
-    let verbosity = matches.occurrences_of("verbose") as usize;
-    stderrlog::new().verbosity(verbosity).init().unwrap();
-
-    // parse p2p server address
-    let p2p_addr = matches
-        .value_of("peer_addr")
-        .unwrap()
-        .parse::<net::SocketAddr>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing P2P server address: {}", e);
+    // load the configuration file and apply command line overrides
+    let mut config = match matches.value_of("config") {
+        Some(path) => Config::load(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Error loading configuration: {}", e);
             process::exit(1);
-        });
+        }),
+        None => Config::default(),
+    };
+    apply_overrides(&mut config, &matches).unwrap_or_else(|e| {
+        eprintln!("Error in configuration: {}", e);
+        process::exit(1);
+    });
+    if matches.is_present("dump_config") {
+        print!("{}", config.to_toml());
+        return;
+    }
 
-    // parse api server address
-    let api_addr = matches
-        .value_of("api_addr")
-        .unwrap()
-        .parse::<net::SocketAddr>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing API server address: {}", e);
-            process::exit(1);
-        });
+    // init logger
+    stderrlog::new().verbosity(config.verbosity().unwrap()).init().unwrap();
+
+    // select the chain parameters
+    let params = ChainParams::for_network(config.network);
+    info!("Running on {}", config.network);
+
+    let p2p_addr = config.p2p_addr.unwrap_or_else(|| {
+        net::SocketAddr::from(([127, 0, 0, 1], params.default_p2p_port))
+    });
+    let api_addr = config.api_addr.unwrap_or_else(|| {
+        net::SocketAddr::from(([127, 0, 0, 1], params.default_api_port))
+    });
 
     // create channels between server and worker
     let (msg_tx, msg_rx) = channel::unbounded();
+    let (disconnect_tx, disconnect_rx) = channel::unbounded();
 
     // start the p2p server
-    let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
-    server_ctx.start().unwrap();
+    let (server_ctx, server) = server::new(p2p_addr, msg_tx, disconnect_tx).unwrap();
+    let server_thread = server_ctx.start().unwrap();
 
-    // start the worker
-    let p2p_workers = matches
-        .value_of("p2p_workers")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap_or_else(|e| {
-            error!("Error parsing P2P workers: {}", e);
+    // restore the state saved at the last shutdown
+    let chain_dir = config.data_dir.join(config.network.to_string());
+    std::fs::create_dir_all(&chain_dir).unwrap_or_else(|e| {
+        error!("Error creating data directory {}: {}", chain_dir.display(), e);
+        process::exit(1);
+    });
+    // light clients keep header-only blocks, which a full node must not load
+    let blocks_path = chain_dir.join(if config.light { "headers.dat" } else { "blocks.dat" });
+    let mempool_path = chain_dir.join("mempool.dat");
+    let mut bc = if config.light {
+        info!("Running as a light client");
+        Blockchain::light(params, &config.watch_addresses().unwrap())
+    } else {
+        Blockchain::with_params(params)
+    };
+    if blocks_path.exists() {
+        bc.load(&blocks_path).unwrap_or_else(|e| {
+            error!("Error loading blocks from {}: {}", blocks_path.display(), e);
             process::exit(1);
         });
+    }
+    let mut mem_pool = match config.mempool.max_size {
+        Some(max_size) => Mempool::with_max_size(max_size),
+        None => Mempool::new(),
+    };
+    if mempool_path.exists() {
+        mem_pool.load(&mempool_path).unwrap_or_else(|e| {
+            error!("Error loading mempool from {}: {}", mempool_path.display(), e);
+            process::exit(1);
+        });
+    }
+    info!("Loaded chain of height {} and {} pending transactions", bc.get_length(), mem_pool.pool.len());
 
-    let bc = Arc::new(Mutex::new(Blockchain::new()));
-    let mem_pool = Arc::new(Mutex::new(Mempool::new()));
-    let worker_ctx = worker::new(
-        p2p_workers,
-        msg_rx,
-        &server,
-        &bc,
-        &mem_pool
-    );
-    worker_ctx.start();
+    // keys derive from the wallet seed, so that the keystore holding the phrase and the addresses
+    // handed out is the only backup needed
+    let network = bc.params().network;
+    let coin_type = bc.params().coin_type;
+    let passphrase = &config.wallet.passphrase;
+    let keystore_path = chain_dir.join("wallet.json");
+    let keystore = if keystore_path.exists() {
+        Some(Keystore::load(&keystore_path, network).unwrap_or_else(|e| {
+            error!("Error loading wallet: {}", e);
+            process::exit(1);
+        }))
+    } else {
+        None
+    };
+    let mut wallet = match (config.mnemonic().unwrap(), keystore) {
+        (Some(mnemonic), Some(keystore)) if keystore.mnemonic != mnemonic.phrase() => {
+            error!("Keystore {} holds another seed than the configured one", keystore_path.display());
+            process::exit(1);
+        }
+        (Some(mnemonic), None) => HdWallet::from_mnemonic(mnemonic, passphrase, coin_type),
+        (_, Some(keystore)) => keystore.wallet(passphrase).unwrap_or_else(|e| {
+            error!("Error loading wallet: {}", e);
+            process::exit(1);
+        }),
+        (None, None) => {
+            info!("No wallet mnemonic configured, using a fresh one kept in {}", keystore_path.display());
+            HdWallet::from_mnemonic(Mnemonic::generate(MNEMONIC_WORDS).unwrap(), passphrase, coin_type)
+        }
+    };
+    let payout_address = match config.payout_address().unwrap() {
+        Some(address) => address,
+        None => wallet.receive_address(),
+    };
+    Keystore::new(network, &wallet, !passphrase.is_empty()).save(&keystore_path).unwrap_or_else(|e| {
+        error!("Error saving wallet: {}", e);
+        process::exit(1);
+    });
+    info!("Mined coinbases pay to {}", Address::new(network, payout_address));
+    let wallet = Arc::new(Mutex::new(wallet));
+
+    // the chain and the mempool publish their changes for the relay, the miner, the metrics and
+    // the API's event streams
+    let events = Arc::new(EventBus::default());
+    bc.set_events(events.clone());
+    mem_pool.set_events(events.clone());
+
+    // start the worker
+    let bc = Arc::new(Mutex::new(bc));
+    let mem_pool = Arc::new(Mutex::new(mem_pool));
+    let signatures = Arc::new(SignatureCache::new(config.signature_cache_size));
+    let orphans = Arc::new(Mutex::new(OrphanPool::default()));
+    let shared = worker::Shared {
+        server: server.clone(),
+        bc: Arc::clone(&bc),
+        mem_pool: Arc::clone(&mem_pool),
+        signatures: Arc::clone(&signatures),
+        orphans: Arc::clone(&orphans),
+    };
+    let worker_ctx = worker::new(config.p2p_workers, msg_rx, disconnect_rx, &shared);
+    let worker_threads = worker_ctx.start();
+    let orphan_thread = worker::adopt_orphans(&events, &shared);
+
+    // announce new blocks and transactions to the peers, and count the events
+    let relay_thread = relay::start(&events, &server, bc.lock().unwrap().is_light());
+    let metrics = Arc::new(Metrics::default());
+    let metrics_thread = metrics.start(&events);
 
     // start the miner
     let (miner_ctx, miner) = miner::new(
-        &server,
+        &events,
         &bc,
-        &mem_pool
+        &mem_pool,
+        Some(payout_address),
     );
-    miner_ctx.start();
+    let miner_thread = miner_ctx.start();
+    if config.miner.auto_start {
+        miner.start(config.miner.lambda, MiningLimit::default());
+    }
 
     // start the generator
     let (generator_ctx, generator) = generator::new(
@@ -106,25 +207,24 @@ fn main() {
         &bc,
         &mem_pool
     );
-    generator_ctx.start();
+    let generator_thread = generator_ctx.start();
+    if config.generator.auto_start {
+        generator.start(config.generator.lambda);
+    }
 
     // connect to known peers
-    if let Some(known_peers) = matches.values_of("known_peer") {
-        let known_peers: Vec<String> = known_peers.map(|x| x.to_owned()).collect();
+    if !config.peers.is_empty() {
+        let known_peers = config.peers.clone();
         let server = server.clone();
+        let bc = Arc::clone(&bc);
         thread::spawn(move || {
-            for peer in known_peers {
+            for addr in known_peers {
                 loop {
-                    let addr = match peer.parse::<net::SocketAddr>() {
-                        Ok(x) => x,
-                        Err(e) => {
-                            error!("Error parsing peer address {}: {}", &peer, e);
-                            break;
-                        }
-                    };
                     match server.connect(addr) {
-                        Ok(_) => {
+                        Ok(peer) => {
                             info!("Connected to outgoing peer {}", &addr);
+                            let version = Handshake::new(&bc.lock().unwrap());
+                            peer.send_version(version);
                             break;
                         }
                         Err(e) => {
@@ -142,15 +242,116 @@ fn main() {
     }
 
 
+    // stop on SIGINT/SIGTERM or a /node/stop request
+    let (stop_tx, stop_rx) = channel::bounded(1);
+    let signal_tx = stop_tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = signal_tx.try_send(());
+    })
+    .unwrap_or_else(|e| {
+        error!("Error setting signal handler: {}", e);
+        process::exit(1);
+    });
+
     // start the API server
-    ApiServer::start(
-        api_addr,
-        &miner,
-        &generator,
-        &server,
-    );
+    let api = ApiServer::start(api_addr, ApiContext {
+        miner: miner.clone(),
+        generator: generator.clone(),
+        network: server.clone(),
+        blockchain: Arc::clone(&bc),
+        mempool: Arc::clone(&mem_pool),
+        signatures: Arc::clone(&signatures),
+        wallet: Arc::clone(&wallet),
+        keystore: keystore_path,
+        events: Arc::clone(&events),
+        metrics: Arc::clone(&metrics),
+        stop_chan: stop_tx.clone(),
+    });
 
-    loop {
-        std::thread::park();
+    stop_rx.recv().unwrap();
+    info!("Shutting down");
+    api.stop();
+    generator.exit();
+    generator_thread.join().unwrap();
+    miner.exit();
+    miner_thread.join().unwrap();
+    // stopping the server closes the worker channel, the workers exit once it is drained
+    server.shutdown();
+    server_thread.join().unwrap();
+    for worker in worker_threads {
+        worker.join().unwrap();
+    }
+    // nothing publishes anymore, the subscribers exit once they have read what is left
+    events.close();
+    relay_thread.join().unwrap();
+    orphan_thread.join().unwrap();
+    metrics_thread.join().unwrap();
+
+    if let Err(e) = bc.lock().unwrap().save(&blocks_path) {
+        error!("Error saving blocks to {}: {}", blocks_path.display(), e);
+    }
+    if let Err(e) = mem_pool.lock().unwrap().save(&mempool_path) {
+        error!("Error saving mempool to {}: {}", mempool_path.display(), e);
+    }
+    info!("Shutdown complete");
+}
+
+/// Apply the command line flags on top of the configuration file.
+fn apply_overrides(config: &mut Config, matches: &clap::ArgMatches) -> Result<(), String> {
+    if matches.is_present("regtest") {
+        config.network = Network::Regtest;
+    } else if let Some(network) = matches.value_of("network") {
+        config.network = network.parse()?;
+    }
+    if let Some(addr) = matches.value_of("peer_addr") {
+        let addr = addr.parse().map_err(|e| format!("error parsing P2P server address: {}", e))?;
+        config.p2p_addr = Some(addr);
+    }
+    if let Some(addr) = matches.value_of("api_addr") {
+        let addr = addr.parse().map_err(|e| format!("error parsing API server address: {}", e))?;
+        config.api_addr = Some(addr);
+    }
+    if let Some(peers) = matches.values_of("known_peer") {
+        for peer in peers {
+            let addr = peer.parse().map_err(|e| format!("error parsing peer address {}: {}", peer, e))?;
+            config.peers.push(addr);
+        }
+    }
+    if let Some(workers) = matches.value_of("p2p_workers") {
+        config.p2p_workers = workers.parse().map_err(|e| format!("error parsing P2P workers: {}", e))?;
+    }
+    if let Some(dir) = matches.value_of("data_dir") {
+        config.data_dir = dir.into();
+    }
+    let verbosity = matches.occurrences_of("verbose") as usize;
+    if verbosity > 0 {
+        let levels = ["error", "warn", "info", "debug", "trace"];
+        config.log_level = levels[verbosity.min(levels.len() - 1)].to_string();
+    }
+    if matches.is_present("light") {
+        config.light = true;
+    }
+    if let Some(addresses) = matches.values_of("watch") {
+        config.watch_addresses.extend(addresses.map(str::to_owned));
+    }
+    if matches.is_present("mine") {
+        config.miner.auto_start = true;
+    }
+    if let Some(lambda) = matches.value_of("miner_lambda") {
+        config.miner.lambda = lambda.parse().map_err(|e| format!("error parsing miner lambda: {}", e))?;
+    }
+    if let Some(address) = matches.value_of("payout_address") {
+        config.miner.payout_address = Some(address.to_owned());
+    }
+    if matches.is_present("generate") {
+        config.generator.auto_start = true;
+    }
+    if let Some(lambda) = matches.value_of("generator_lambda") {
+        config.generator.lambda = lambda.parse().map_err(|e| format!("error parsing generator lambda: {}", e))?;
+    }
+    if let Some(size) = matches.value_of("mempool_max_size") {
+        let size = size.parse().map_err(|e| format!("error parsing mempool max size: {}", e))?;
+        config.mempool.max_size = Some(size);
     }
+    config.validate()
 }
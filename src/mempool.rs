@@ -1,29 +1,81 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
 use std::ptr::addr_of_mut;
+use std::sync::Arc;
 use crate::crypto::hash::{H256, Hashable};
+use crate::blockchain::Blockchain;
+use crate::crypto::batch::SignatureVerifier;
 use crate::signedtrans::SignedTrans;
+use crate::events::{Event, EventBus};
 
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Mempool {
     pub pool: HashMap<H256, SignedTrans>,
+    max_size: Option<usize>, // maximum number of transactions, unlimited if None
+    #[serde(skip)]
+    events: Arc<EventBus>, // receives the transactions added and removed
 }
 
 impl Mempool {
     pub fn new() -> Self{
         let m =Mempool {
-            pool: HashMap::new()
+            pool: HashMap::new(),
+            max_size: None,
+            events: Arc::default(),
         };
         m
     }
 
-    pub fn add(&mut self, signed: &SignedTrans) {
-        let map = self.clone().pool;
+    /// Create a mempool holding at most `max_size` transactions
+    pub fn with_max_size(max_size: usize) -> Self {
+        Mempool {
+            pool: HashMap::new(),
+            max_size: Some(max_size),
+            events: Arc::default(),
+        }
+    }
+
+    /// Publish the transactions added and removed to `events`
+    pub fn set_events(&mut self, events: Arc<EventBus>) {
+        self.events = events;
+    }
+
+    /// Add a transaction, returns false if it is already present or the pool is full
+    pub fn add(&mut self, signed: &SignedTrans) -> bool {
         let hash = signed.hash();
-        if !map.contains_key(&hash){
-            self.pool.insert(hash, signed.clone());
-        };
+        if self.pool.contains_key(&hash) || self.is_full() {
+            return false;
+        }
+        self.pool.insert(hash, signed.clone());
+        self.events.publish(Event::TxAddedToMempool { txid: hash });
+        true
+    }
+
+    /// Check a transaction received from a peer or a client against the chain and, if valid,
+    /// add it to the pool and to the chain state.
+    pub fn accept(&mut self, bc: &mut Blockchain, tx: &SignedTrans, verifier: &dyn SignatureVerifier) -> Result<(), String> {
+        if self.pool.contains_key(&tx.hash()) {
+            return Err("transaction already in the mempool".to_string());
+        }
+        tx.verify(|hash| bc.spent_outputs(hash), bc.get_length() + 1, verifier)?;
+        if tx.transaction.output_val() > tx.transaction.input_val() {
+            return Err("transaction spends more than its inputs".to_string());
+        }
+        if !self.add(tx) {
+            return Err("mempool is full".to_string());
+        }
+        bc.update_state(tx, self.pool.len());
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        match self.max_size {
+            Some(max) => self.pool.len() >= max,
+            None => false,
+        }
     }
 
     pub fn remove(&mut self, signed: &SignedTrans) {
@@ -31,10 +83,29 @@ impl Mempool {
         let hash = signed.hash();
         if map.contains_key(&hash) {
             self.pool.remove(&hash);
+            self.events.publish(Event::TxRemoved { txid: hash });
         }
         return
     }
 
+    /// Write the pending transactions to `path`.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let txs: Vec<&SignedTrans> = self.pool.values().collect();
+        let encoded = bincode::serialize(&txs).map_err(Error::other)?;
+        std::fs::write(path, encoded)
+    }
+
+    /// Add the transactions written by `save`, as far as the pool has room for them.
+    pub fn load(&mut self, path: &Path) -> std::io::Result<()> {
+        let encoded = std::fs::read(path)?;
+        let txs: Vec<SignedTrans> = bincode::deserialize(&encoded)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for tx in txs.iter() {
+            self.add(tx);
+        }
+        Ok(())
+    }
+
     pub fn print(&self) {
         println!("mempool: size:{:?}", self.pool.clone().len());
     }
@@ -0,0 +1,104 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use log::info;
+use crate::events::{Event, EventBus, EventKind};
+
+/// Counts of the chain and mempool events since the node started, filled from the event bus.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    blocks_connected: AtomicU64,
+    blocks_disconnected: AtomicU64,
+    reorgs: AtomicU64,
+    deepest_reorg: AtomicU64,
+    txs_added: AtomicU64,
+    txs_removed: AtomicU64,
+}
+
+/// Snapshot of the counters reported by the `/metrics` endpoint.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub blocks_connected: u64,
+    pub blocks_disconnected: u64,
+    pub reorgs: u64,
+    /// Most blocks disconnected by a single reorg
+    pub deepest_reorg: u64,
+    pub txs_added: u64,
+    pub txs_removed: u64,
+}
+
+impl Metrics {
+    /// Count the events published on `events` from now on, until the bus is closed.
+    pub fn start(self: &Arc<Self>, events: &EventBus) -> thread::JoinHandle<()> {
+        let received = events.subscribe(&EventKind::ALL);
+        let metrics = Arc::clone(self);
+        thread::spawn(move || {
+            for event in received.iter() {
+                metrics.record(&event);
+            }
+            info!("Metrics thread exited");
+        })
+    }
+
+    pub fn record(&self, event: &Event) {
+        let counter = match event {
+            Event::BlockConnected { .. } => &self.blocks_connected,
+            Event::BlockDisconnected { .. } => &self.blocks_disconnected,
+            Event::TxAddedToMempool { .. } => &self.txs_added,
+            Event::TxRemoved { .. } => &self.txs_removed,
+            Event::Reorg { disconnected, .. } => {
+                self.deepest_reorg.fetch_max(*disconnected as u64, Ordering::Relaxed);
+                &self.reorgs
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            blocks_connected: self.blocks_connected.load(Ordering::Relaxed),
+            blocks_disconnected: self.blocks_disconnected.load(Ordering::Relaxed),
+            reorgs: self.reorgs.load(Ordering::Relaxed),
+            deepest_reorg: self.deepest_reorg.load(Ordering::Relaxed),
+            txs_added: self.txs_added.load(Ordering::Relaxed),
+            txs_removed: self.txs_removed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::block::generate_random_block;
+    use crate::blockchain::Blockchain;
+    use crate::crypto::hash::Hashable;
+
+    #[test]
+    fn counts_chain_events() {
+        let events = Arc::new(EventBus::default());
+        let metrics = Arc::new(Metrics::default());
+        let thread = metrics.start(&events);
+        let mut bc = Blockchain::new();
+        bc.set_events(events.clone());
+        let genesis = bc.tip();
+        let main = generate_random_block(&genesis);
+        let fork = generate_random_block(&genesis);
+        let fork2 = generate_random_block(&fork.hash());
+        bc.insert(&main);
+        bc.insert(&fork);
+        bc.insert(&fork2);
+        events.publish(Event::TxAddedToMempool { txid: [1u8; 32].into() });
+        events.close();
+        thread.join().unwrap();
+
+        assert_eq!(metrics.snapshot(), MetricsSnapshot {
+            blocks_connected: 3,
+            blocks_disconnected: 1,
+            reorgs: 1,
+            deepest_reorg: 1,
+            txs_added: 1,
+            txs_removed: 0,
+        });
+    }
+}
@@ -1,46 +1,175 @@
 use std::sync::{Arc, Mutex};
-use std::time::SystemTime;
+use std::time::{Duration, Instant};
 use rand::Rng;
-use crate::network::server::Handle as ServerHandle;
+use std::collections::HashMap;
+use serde::Serialize;
 use crate::blockchain::Blockchain;
-use crate::block::Block;
+use crate::block::{Block, Header};
+use crate::crypto::batch::SignatureVerifier;
 use crate::crypto::merkle::MerkleTree;
+use crate::encoding::Encode;
 use crate::signedtrans::SignedTrans;
-use crate::network::message::Message;
+use crate::events::{Event, EventBus, EventKind};
 use crate::mempool::Mempool;
 use crate::crypto::key_pair;
+use crate::transaction::coin_base;
+use crate::params::Network;
 
 
-use log::info;
+use log::{debug, info};
 
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use crossbeam::channel::{self, select, unbounded, Receiver, Sender, TryRecvError};
 use std::time;
 
 use std::thread;
 use ring::signature::{Ed25519KeyPair, KeyPair};
-use crate::crypto::hash::{H160, Hashable};
+use crate::crypto::hash::{H160, H256, Hashable};
+
+/// How long the miner works on a template before refreshing its timestamp
+const TEMPLATE_REFRESH: Duration = Duration::from_secs(1);
 
 enum ControlSignal {
-    Start(u64), // the number controls the lambda of interval between block generation
+    Start(u64, MiningLimit), // the number controls the lambda of interval between block generation
+    Pause,
+    Resume,
+    Stop,
     Exit,
 }
 
 enum OperatingState {
-    Paused,
+    Stopped,
+    Paused(u64),
     Run(u64),
     ShutDown,
 }
 
+/// Optional bounds on a mining run; the miner stops by itself once any of them is reached.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MiningLimit {
+    pub duration: Option<Duration>,
+    pub blocks: Option<u32>,
+}
+
+/// Counters shared between the miner thread and its handles.
+#[derive(Default)]
+struct Stats {
+    state: &'static str,
+    lambda: u64,
+    hashes: u64,
+    running_time: Duration,
+    mined_blocks: Vec<H256>,
+}
+
+/// Snapshot of the miner reported by the `/miner/status` endpoint.
+#[derive(Serialize, Debug, Clone)]
+pub struct MinerStatus {
+    pub state: String,
+    pub lambda: u64,
+    pub hashes: u64,
+    pub hash_rate: f64,
+    pub blocks_mined: usize,
+    pub blocks_orphaned: usize,
+}
+
+/// Work handed out to external miners: a header with a zero nonce plus the transactions that
+/// its merkle root commits to.
+#[derive(Serialize, Debug, Clone)]
+pub struct BlockTemplate {
+    pub header: Header,
+    pub transactions: Vec<SignedTrans>,
+}
+
+/// Pick mempool transactions, in hash order, until the block would grow past `max_size` bytes.
+/// `base` is the size of the block before any mempool transaction is added.
+fn select_transactions(mp: &Mempool, base: usize, max_size: usize) -> Vec<SignedTrans> {
+    let mut pending: Vec<&SignedTrans> = mp.pool.values().collect();
+    pending.sort_by_key(|tx| tx.hash());
+    let mut size = base;
+    let mut selected = Vec::new();
+    for tx in pending {
+        let tx_size = tx.encode().len() - 1; // without the version byte
+        if size + tx_size > max_size {
+            break;
+        }
+        size += tx_size;
+        selected.push(tx.clone());
+    }
+    selected
+}
+
+/// Assemble an unsolved block (nonce 0) on top of the tip: a coinbase paying the block subsidy to
+/// `address`, followed by mempool transactions up to the block size limit.
+fn assemble_block(bc: &Blockchain, mp: &Mempool, address: &H160, timestamp: u128) -> Block {
+    let parent = bc.tip();
+    let height = bc.get_length() + 1;
+    let difficulty = bc.get_difficulty();
+
+    let coinbase = SignedTrans {
+        transaction: coin_base(address, bc.params().subsidy(height), height),
+        signature: Vec::new(),
+        public_key: Vec::new(),
+    };
+    let mut trans = vec![coinbase];
+    let builder = Block::builder()
+        .version(bc.block_version(&parent))
+        .height(height)
+        .parent(parent)
+        .difficulty(difficulty)
+        .timestamp(timestamp);
+    let base = builder.clone().transactions(trans.clone()).build().encode().len();
+    let mut tree = MerkleTree::new(&trans);
+    for tx in select_transactions(mp, base, bc.params().max_block_size) {
+        tree.push(&tx);
+        trans.push(tx);
+    }
+    builder.merkle_root(tree.root()).transactions(trans).build()
+}
+
+/// Build a template on top of the current tip whose coinbase pays `address`, timestamped with
+/// network-adjusted time, or just after the median time past if that is later.
+pub fn block_template(bc: &Blockchain, mp: &Mempool, address: &H160) -> BlockTemplate {
+    let timestamp = bc.adjusted_time().max(bc.median_time_past(&bc.tip()) + 1);
+    let blk = assemble_block(bc, mp, address, timestamp);
+    BlockTemplate { header: blk.header, transactions: blk.content }
+}
+
+/// Mine `n` blocks on top of the tip synchronously, each paying the coinbase to `address`.
+/// Nonces count up from zero and timestamps follow the parent's, so the result only depends on
+/// the chain and mempool contents; meant for regtest, where any nonce meets the difficulty.
+pub fn generate_blocks(bc: &mut Blockchain, mp: &mut Mempool, n: usize, address: &H160) -> Vec<Block> {
+    let mut mined = Vec::new();
+    for _ in 0..n {
+        let timestamp = bc.blocks[&bc.tip()].0.header.get_create_time() + 1;
+        let mut blk = assemble_block(bc, mp, address, timestamp);
+        let mut nonce: u32 = 0;
+        while blk.hash() > blk.get_difficulty() {
+            nonce += 1;
+            blk.header.set_nonce(nonce);
+        }
+        for tx in blk.content.iter() {
+            mp.remove(tx);
+        }
+        bc.insert(&blk);
+        mined.push(blk);
+    }
+    mined
+}
+
 pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
     operating_state: OperatingState,
-    server: ServerHandle,
+    /// New tips and mempool changes, after which the template is rebuilt
+    chain_events: Receiver<Event>,
+    /// The template being worked on and when it was built
+    template: Option<(BlockTemplate, Instant)>,
     bc: Arc<Mutex<Blockchain>>,
     mp: Arc<Mutex<Mempool>>,
-    mined: u32,
-    inserted: u32,
-    start_time: SystemTime,
+    stats: Arc<Mutex<Stats>>,
+    limit: MiningLimit,
+    run_start: Instant,
+    run_blocks: u32,
+    last_tick: Instant,
     key: Ed25519KeyPair,
     self_address:H160,
 }
@@ -49,30 +178,50 @@ pub struct Context {
 pub struct Handle {
     /// Channel for sending signal to the miner thread
     control_chan: Sender<ControlSignal>,
+    stats: Arc<Mutex<Stats>>,
+    bc: Arc<Mutex<Blockchain>>,
+    mp: Arc<Mutex<Mempool>>,
+    /// Address the coinbase of every block we build pays to
+    payout_address: H160,
+    /// Templates handed out to external miners, keyed by merkle root
+    templates: Arc<Mutex<HashMap<H256, BlockTemplate>>>,
 }
 
+/// Create a miner paying its rewards to `payout_address`, or to a fresh key of its own when none is given.
 pub fn new(
-    server: &ServerHandle,
+    events: &EventBus,
     bc: &Arc<Mutex<Blockchain>>,
-    mp: &Arc<Mutex<Mempool>>
+    mp: &Arc<Mutex<Mempool>>,
+    payout_address: Option<H160>,
 ) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
+    let key = key_pair::random();
+    let self_address = payout_address.unwrap_or_else(|| H160::hash(key.public_key().as_ref()));
+    let stats = Arc::new(Mutex::new(Stats { state: "stopped", ..Default::default() }));
 
     let ctx = Context {
         control_chan: signal_chan_receiver,
-        operating_state: OperatingState::Paused,
-        server: server.clone(),
+        operating_state: OperatingState::Stopped,
+        chain_events: events.subscribe(&[EventKind::BlockConnected, EventKind::TxAddedToMempool, EventKind::TxRemoved]),
+        template: None,
         bc: Arc::clone(bc),
         mp: Arc::clone(mp),
-        mined: 0,
-        inserted: 0,
-        start_time: SystemTime::now(),
-        key: key_pair::random(),
-        self_address: Default::default()
+        stats: Arc::clone(&stats),
+        limit: MiningLimit::default(),
+        run_start: Instant::now(),
+        run_blocks: 0,
+        last_tick: Instant::now(),
+        key,
+        self_address,
     };
 
     let handle = Handle {
         control_chan: signal_chan_sender,
+        stats,
+        bc: Arc::clone(bc),
+        mp: Arc::clone(mp),
+        payout_address: self_address,
+        templates: Arc::new(Mutex::new(HashMap::new())),
     };
 
     (ctx, handle)
@@ -80,53 +229,228 @@ pub fn new(
 
 impl Handle {
     pub fn exit(&self) {
-        self.control_chan.send(ControlSignal::Exit).unwrap();
+        if self.control_chan.send(ControlSignal::Exit).is_err() {
+            debug!("Miner already exited");
+        }
     }
 
-    pub fn start(&self, lambda: u64) {
-        self.control_chan
-            .send(ControlSignal::Start(lambda))
-            .unwrap();
+    pub fn start(&self, lambda: u64, limit: MiningLimit) {
+        if self.control_chan.send(ControlSignal::Start(lambda, limit)).is_err() {
+            debug!("Miner exited, not starting");
+        }
+    }
+
+    pub fn pause(&self) {
+        if self.control_chan.send(ControlSignal::Pause).is_err() {
+            debug!("Miner exited, not pausing");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.control_chan.send(ControlSignal::Resume).is_err() {
+            debug!("Miner exited, not resuming");
+        }
+    }
+
+    /// Stop the current mining run; the miner thread stays alive and can be started again.
+    pub fn stop(&self) {
+        if self.control_chan.send(ControlSignal::Stop).is_err() {
+            debug!("Miner exited, not stopping");
+        }
+    }
+
+    pub fn status(&self) -> MinerStatus {
+        let stats = self.stats.lock().unwrap();
+        let bc = self.bc.lock().unwrap();
+        let secs = stats.running_time.as_secs_f64();
+        let hash_rate = if secs > 0.0 { stats.hashes as f64 / secs } else { 0.0 };
+        // a block we mined is orphaned once it is no longer on the longest chain
+        let blocks_orphaned = stats.mined_blocks.iter().filter(|h| !bc.contain(**h)).count();
+        MinerStatus {
+            state: stats.state.to_string(),
+            lambda: stats.lambda,
+            hashes: stats.hashes,
+            hash_rate,
+            blocks_mined: stats.mined_blocks.len(),
+            blocks_orphaned,
+        }
+    }
+
+    /// Hand out a new template for external mining.
+    pub fn template(&self) -> BlockTemplate {
+        let bc = self.bc.lock().unwrap();
+        let template = block_template(&bc, &self.mp.lock().unwrap(), &self.payout_address);
+        let tip = bc.tip();
+        drop(bc);
+        let mut templates = self.templates.lock().unwrap();
+        // templates built on an old tip can no longer be submitted
+        templates.retain(|_, t| t.header.parent == tip);
+        templates.insert(template.header.get_merkle_root(), template.clone());
+        template
+    }
+
+    /// Accept a header solved by an external miner, assemble the block from its template and
+    /// insert it into the chain once its transactions verify. Returns the hash of the new block.
+    pub fn submit(&self, mut header: Header, nonce: u32, verifier: &dyn SignatureVerifier) -> Result<H256, String> {
+        let template = match self.templates.lock().unwrap().get(&header.get_merkle_root()) {
+            Some(t) => t.clone(),
+            None => return Err("unknown template".to_string()),
+        };
+        // the same rule as for the blocks we mine ourselves
+        if template.transactions.len() <= 1 {
+            return Err("template carries no transactions besides the coinbase".to_string());
+        }
+        header.set_nonce(nonce);
+        let blk = Block { header, content: template.transactions };
+
+        let mut bc = self.bc.lock().unwrap();
+        if bc.blocks.contains_key(&blk.hash()) {
+            return Err("duplicate block".to_string());
+        }
+        bc.validate(&blk)?;
+        bc.verify_transactions(&blk, verifier)?;
+        let mut mp = self.mp.lock().unwrap();
+        for tx in blk.content.iter() {
+            mp.remove(tx);
+        }
+        drop(mp);
+        bc.insert(&blk);
+        drop(bc);
+        info!("Accepted externally mined block {:?}", blk.hash());
+        Ok(blk.hash())
     }
 
+    /// Mine `n` blocks right away paying `address`; only available on regtest.
+    pub fn generate(&self, n: usize, address: &H160) -> Result<Vec<H256>, String> {
+        let mut bc = self.bc.lock().unwrap();
+        if bc.params().network != Network::Regtest {
+            return Err("block generation is only available in regtest mode".to_string());
+        }
+        let blocks = generate_blocks(&mut bc, &mut self.mp.lock().unwrap(), n, address);
+        Ok(blocks.iter().map(|b| b.hash()).collect())
+    }
 }
 
 impl Context {
-    pub fn start(mut self) {
-        thread::Builder::new()
+    pub fn start(mut self) -> thread::JoinHandle<()> {
+        let handle = thread::Builder::new()
             .name("miner".to_string())
             .spawn(move || {
                 self.miner_loop();
             })
             .unwrap();
-        info!("Miner initialized into paused mode");
+        info!("Miner initialized into stopped mode");
+        handle
     }
 
     fn handle_control_signal(&mut self, signal: ControlSignal) {
+        // account for the time spent running before the state changes
+        self.record_running_time();
         match signal {
             ControlSignal::Exit => {
                 info!("Miner shutting down");
-                self.operating_state = OperatingState::ShutDown;
+                self.set_state(OperatingState::ShutDown);
             }
-            ControlSignal::Start(i) => {
+            ControlSignal::Start(i, limit) => {
                 info!("Miner starting in continuous mode with lambda {}", i);
-                self.start_time = SystemTime::now();
-                // println!("---------- start :{:?}", SystemTime::now());
-                self.operating_state = OperatingState::Run(i);
+                self.limit = limit;
+                self.run_start = Instant::now();
+                self.run_blocks = 0;
+                self.set_state(OperatingState::Run(i));
+            }
+            ControlSignal::Pause => {
+                if let OperatingState::Run(i) = self.operating_state {
+                    info!("Miner paused");
+                    self.set_state(OperatingState::Paused(i));
+                }
+            }
+            ControlSignal::Resume => {
+                if let OperatingState::Paused(i) = self.operating_state {
+                    info!("Miner resumed with lambda {}", i);
+                    self.set_state(OperatingState::Run(i));
+                }
+            }
+            ControlSignal::Stop => {
+                info!("Miner stopped");
+                self.set_state(OperatingState::Stopped);
             }
         }
     }
 
-    fn miner_loop(&mut self) {
-        let mut mined_size:usize = 0;
+    fn set_state(&mut self, state: OperatingState) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.state = match state {
+            OperatingState::Stopped => "stopped",
+            OperatingState::Paused(_) => "paused",
+            OperatingState::Run(_) => "running",
+            OperatingState::ShutDown => "shutdown",
+        };
+        match state {
+            OperatingState::Run(i) => {
+                stats.lambda = i;
+                self.last_tick = Instant::now();
+            }
+            OperatingState::Paused(i) => stats.lambda = i,
+            _ => {}
+        }
+        self.operating_state = state;
+    }
+
+    fn record_running_time(&mut self) {
+        if let OperatingState::Run(_) = self.operating_state {
+            let now = Instant::now();
+            self.stats.lock().unwrap().running_time += now.duration_since(self.last_tick);
+            self.last_tick = now;
+        }
+    }
+
+    /// Whether the current run has reached its duration or block-count limit.
+    fn limit_reached(&self) -> bool {
+        if let Some(duration) = self.limit.duration {
+            if self.run_start.elapsed() >= duration {
+                return true;
+            }
+        }
+        if let Some(blocks) = self.limit.blocks {
+            if self.run_blocks >= blocks {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The template to work on, rebuilt after a new tip or a mempool change, and once it is
+    /// older than `TEMPLATE_REFRESH`.
+    fn current_template(&mut self) -> &BlockTemplate {
+        if self.chain_events.try_iter().count() > 0 {
+            self.template = None;
+        }
+        if self.template.as_ref().is_some_and(|(_, built)| built.elapsed() >= TEMPLATE_REFRESH) {
+            self.template = None;
+        }
+        if self.template.is_none() {
+            let bc = self.bc.lock().unwrap();
+            let template = block_template(&bc, &self.mp.lock().unwrap(), &self.self_address);
+            self.template = Some((template, Instant::now()));
+        }
+        &self.template.as_ref().unwrap().0
+    }
 
+    fn miner_loop(&mut self) {
         // main mining loop
         loop {
             // check and react to control signals
             match self.operating_state {
-                OperatingState::Paused => {
-                    let signal = self.control_chan.recv().unwrap();
-                    self.handle_control_signal(signal);
+                OperatingState::Stopped | OperatingState::Paused(_) => {
+                    // keep up with the chain while idle, so that its events don't pile up
+                    select! {
+                        recv(self.control_chan) -> signal => self.handle_control_signal(signal.unwrap()),
+                        recv(self.chain_events) -> event => match event {
+                            Ok(_) => self.template = None,
+                            // the bus is closed, the node is shutting down
+                            Err(_) => self.chain_events = channel::never(),
+                        },
+                    }
                     continue;
                 }
                 OperatingState::ShutDown => {
@@ -140,63 +464,41 @@ impl Context {
                     Err(TryRecvError::Disconnected) => panic!("Miner control channel detached"),
                 },
             }
-            if let OperatingState::ShutDown = self.operating_state {
-                return;
+            if !matches!(self.operating_state, OperatingState::Run(_)) {
+                continue;
             }
 
-            // get parent
-            let mut bc = self.bc.lock().unwrap();
-            let mp = self.mp.lock().unwrap().clone().pool;
-            let parent = bc.tip();
-
-            // get timestamp
-            let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
-
-            // get difficulty
-            let difficulty = bc.get_difficulty();
-
-            // generate merkle root
-            let mut trans = Vec::<SignedTrans>::new();
-            for (_,val) in mp.clone() {
-                trans.push(val);
-            }
-            drop(mp);
-            let merkle_tree = MerkleTree::new(&trans);
-            let root = merkle_tree.root();
+            let template = self.current_template();
+            let difficulty = template.header.difficulty;
 
             // generate nonce
-            let nonce = rand::thread_rng().gen::<u32>();
-
-            let blk = Block::new(parent,nonce,difficulty,timestamp,root,trans.clone());
+            let mut header = template.header.clone();
+            header.set_nonce(rand::thread_rng().gen::<u32>());
+            let blk = Block { header, content: template.transactions.clone() };
 
-            self.mined += 1;
-            if self.mined % 1000 == 0 {
-                println!("{:?} {}", difficulty, self.mined);
-            }
-            if blk.hash() <= difficulty && !trans.is_empty() {
-                for tx in blk.clone().content {
-                    self.mp.lock().unwrap().remove(&tx);
-                }
-                bc.insert(&blk);
-                self.inserted += 1;
-
-                // broadcast to peers
-                let mut block_vec = Vec::new();
-                block_vec.push(blk.hash());
-                let msg = Message::NewBlockHashes(block_vec);
-                self.server.broadcast(msg);
-
-                mined_size += serde_json::to_string(&blk).unwrap().len();
-                if self.inserted % 100 == 0 {
-                    println!("avg block size:{:?}", mined_size as u32/self.mined);
+            self.stats.lock().unwrap().hashes += 1;
+            // only blocks carrying transactions besides the coinbase are worth publishing
+            if blk.hash() <= difficulty && blk.content.len() > 1 {
+                let mut bc = self.bc.lock().unwrap();
+                // the tip may have moved before its event was read
+                if bc.tip() == blk.header.parent {
+                    let mut mp = self.mp.lock().unwrap();
+                    for tx in blk.content.iter() {
+                        mp.remove(tx);
+                    }
+                    drop(mp);
+                    bc.insert(&blk);
+                    self.stats.lock().unwrap().mined_blocks.push(blk.hash());
+                    self.run_blocks += 1;
                 }
+                self.template = None;
             }
-            drop(bc);
 
-            if SystemTime::now().duration_since(self.start_time).unwrap().as_secs() >= 300 {
-                println!("---------- result : {:?}, {}/{}, {:?}", difficulty, self.inserted, self.mined, SystemTime::now());
-                println!("========== avg block size:{:?}/{:?}={:?}", mined_size, self.inserted, mined_size as u32/self.inserted);
-                break
+            self.record_running_time();
+            if self.limit_reached() {
+                info!("Miner reached its run limit after {} blocks", self.run_blocks);
+                self.set_state(OperatingState::Stopped);
+                continue;
             }
 
             if let OperatingState::Run(i) = self.operating_state {
@@ -208,3 +510,84 @@ impl Context {
         }
     }
 }
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::signedtrans::generate_random_signedtrans;
+    use crate::params::ChainParams;
+    use crate::crypto::batch::DirectVerifier;
+
+    /// Find a nonce solving `header`
+    fn solve(header: &Header) -> u32 {
+        let mut header = header.clone();
+        (0..).find(|nonce| {
+            header.set_nonce(*nonce);
+            header.hash() <= header.difficulty
+        }).unwrap()
+    }
+
+    #[test]
+    fn submit_checks_content_and_transactions() {
+        let bc = Arc::new(Mutex::new(Blockchain::with_params(ChainParams::regtest())));
+        let mp = Arc::new(Mutex::new(Mempool::new()));
+        let (_ctx, handle) = new(&EventBus::default(), &bc, &mp, Some([7u8; 20].into()));
+
+        let empty = handle.template();
+        let result = handle.submit(empty.header.clone(), solve(&empty.header), &DirectVerifier);
+        assert_eq!(result, Err("template carries no transactions besides the coinbase".to_string()));
+
+        // a transaction spending outputs that do not exist
+        mp.lock().unwrap().add(&generate_random_signedtrans());
+        let invalid = handle.template();
+        assert!(handle.submit(invalid.header.clone(), solve(&invalid.header), &DirectVerifier).is_err());
+        assert_eq!(bc.lock().unwrap().get_length(), 0);
+    }
+
+    #[test]
+    fn generate_is_deterministic() {
+        let address: H160 = [7u8; 20].into();
+        let mut bc1 = Blockchain::with_params(ChainParams::regtest());
+        let mut bc2 = Blockchain::with_params(ChainParams::regtest());
+        let blocks1 = generate_blocks(&mut bc1, &mut Mempool::new(), 5, &address);
+        let blocks2 = generate_blocks(&mut bc2, &mut Mempool::new(), 5, &address);
+        let hashes1: Vec<H256> = blocks1.iter().map(|b| b.hash()).collect();
+        let hashes2: Vec<H256> = blocks2.iter().map(|b| b.hash()).collect();
+        assert_eq!(hashes1, hashes2);
+        assert_eq!(bc1.get_length(), 5);
+        assert_eq!(bc1.tip(), hashes1[4]);
+        assert_eq!(bc1.all_blocks_in_longest_chain()[1..], hashes1[..]);
+    }
+
+    #[test]
+    fn generate_drains_mempool() {
+        let mut bc = Blockchain::with_params(ChainParams::regtest());
+        let mut mp = Mempool::new();
+        let tx = generate_random_signedtrans();
+        mp.add(&tx);
+        let blocks = generate_blocks(&mut bc, &mut mp, 2, &Default::default());
+        assert!(mp.pool.is_empty());
+        assert_eq!(blocks[0].content.len(), 2);
+        assert_eq!(blocks[0].content[1].hash(), tx.hash());
+        assert_eq!(blocks[1].content.len(), 1);
+        for blk in blocks.iter() {
+            assert!(blk.hash() <= blk.get_difficulty());
+        }
+    }
+
+    #[test]
+    fn template_starts_with_a_coinbase() {
+        let bc = Blockchain::with_params(ChainParams::regtest());
+        let mut mp = Mempool::new();
+        let tx = generate_random_signedtrans();
+        mp.add(&tx);
+        let address: H160 = [7u8; 20].into();
+        let template = block_template(&bc, &mp, &address);
+        let coinbase = &template.transactions[0].transaction;
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.outputs[0].address, address);
+        assert_eq!(coinbase.outputs[0].balance, bc.params().subsidy(1));
+        assert_eq!(template.transactions[1].hash(), tx.hash());
+        assert_eq!(template.header.get_height(), 1);
+    }
+}
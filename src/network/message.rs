@@ -1,7 +1,10 @@
 use serde::{Serialize, Deserialize};
-use crate::crypto::hash::{H160, H256};
-use crate::block::Block;
+use crate::crypto::hash::{H160, H256, Hashable};
+use crate::block::{Block, Header};
+use crate::blockchain::{Blockchain, TxProof};
+use crate::filter::BlockFilter;
 use crate::signedtrans::{SignedTrans};
+use std::time::SystemTime;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
@@ -13,5 +16,42 @@ pub enum Message {
     NewTransactionHashes(Vec<H256>),
     GetTransactions(Vec<H256>),
     Transactions(Vec<SignedTrans>),
-    Address(Vec<H160>)
+    Address(Vec<H160>),
+    Version(Handshake),
+    VersionAck(Handshake),
+    GetMerkleProof(Vec<H256>),
+    MerkleProof(Vec<TxProof>),
+    /// Block locator of a light client asking for the headers that follow it
+    GetHeaders(Vec<H256>),
+    Headers(Vec<Header>),
+    GetAddressTransactions(Vec<H160>),
+    AddressTransactions(Vec<(SignedTrans, TxProof)>),
+    /// Block hashes whose compact filters are wanted
+    GetFilters(Vec<H256>),
+    Filters(Vec<BlockFilter>),
+}
+
+/// Sent by both sides when a connection is set up; peers on another network are dropped.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Handshake {
+    pub magic: [u8; 4],
+    pub genesis: H256,
+    pub height: u32,
+    pub timestamp: u128,
+}
+
+impl Handshake {
+    pub fn new(bc: &Blockchain) -> Self {
+        Handshake {
+            magic: bc.params().magic,
+            genesis: bc.params().genesis_block().hash(),
+            height: bc.get_length(),
+            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis(),
+        }
+    }
+
+    /// Whether the peer that sent this handshake runs the same chain as `bc`.
+    pub fn is_compatible(&self, bc: &Blockchain) -> bool {
+        self.magic == bc.params().magic && self.genesis == bc.params().genesis_block().hash()
+    }
 }
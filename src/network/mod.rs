@@ -2,3 +2,4 @@ pub mod message;
 pub mod peer;
 pub mod server;
 pub mod worker;
+pub mod relay;
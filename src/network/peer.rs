@@ -4,7 +4,8 @@ use mio;
 use mio_extras::channel;
 use std::convert::TryInto;
 use std::io::{Read, Write};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Instant;
 
 enum DecodeState {
     Length,
@@ -184,9 +185,11 @@ pub fn new(
     let handle = Handle {
         write_queue: write_sender,
         addr,
+        handshake: Arc::default(),
     };
     let ctx = Context {
         addr,
+        connected: Instant::now(),
         stream,
         reader: read_ctx,
         writer: write_ctx,
@@ -204,6 +207,8 @@ pub enum Direction {
 
 pub struct Context {
     pub addr: std::net::SocketAddr,
+    /// When the connection was set up, to drop peers that never complete the handshake
+    pub connected: Instant,
     pub stream: mio::net::TcpStream,
     pub reader: ReadContext,
     pub writer: WriteContext,
@@ -211,13 +216,63 @@ pub struct Context {
     pub direction: Direction,
 }
 
+/// Progress of the version handshake with a peer. Each side sends its `Version`, the outgoing
+/// one first, and acknowledges the other's; only then do other messages count.
+#[derive(Debug, Default)]
+struct HandshakeProgress {
+    version_sent: bool,
+    version_received: bool,
+    ack_received: bool,
+}
+
+impl HandshakeProgress {
+    fn is_established(&self) -> bool {
+        self.version_sent && self.version_received && self.ack_received
+    }
+}
+
 #[derive(Clone)]
 pub struct Handle {
     addr: std::net::SocketAddr,
     write_queue: channel::Sender<Vec<u8>>,
+    /// Shared by every clone of the handle, so by every worker thread
+    handshake: Arc<Mutex<HandshakeProgress>>,
 }
 
 impl Handle {
+    /// Send our version, unless it was sent already.
+    pub fn send_version(&self, version: message::Handshake) {
+        let mut handshake = self.handshake.lock().unwrap();
+        if !handshake.version_sent {
+            handshake.version_sent = true;
+            self.write(message::Message::Version(version));
+        }
+    }
+
+    /// Record the peer's verified version, returns whether it completes the handshake.
+    pub fn version_received(&self) -> bool {
+        let mut handshake = self.handshake.lock().unwrap();
+        let established = handshake.is_established();
+        handshake.version_received = true;
+        !established && handshake.is_established()
+    }
+
+    /// Record the peer's verified acknowledgement, returns whether it completes the handshake.
+    pub fn ack_received(&self) -> bool {
+        let mut handshake = self.handshake.lock().unwrap();
+        let established = handshake.is_established();
+        handshake.ack_received = true;
+        !established && handshake.is_established()
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.handshake.lock().unwrap().is_established()
+    }
+
+    pub fn addr(&self) -> std::net::SocketAddr {
+        self.addr
+    }
+
     pub fn write(&self, msg: message::Message) {
         // TODO: return result
         let buffer = bincode::serialize(&msg).unwrap();
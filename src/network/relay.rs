@@ -0,0 +1,40 @@
+use super::message::Message;
+use crate::events::{Event, EventBus, EventKind};
+use crate::network::server::Handle as ServerHandle;
+use log::info;
+use std::thread;
+
+/// Announce to the peers the blocks joining the longest chain and the transactions accepted
+/// into the mempool, whichever component caused them. A `light` client only has the headers of
+/// its blocks, which peers could not download, so it only announces transactions. The thread
+/// exits once the bus is closed.
+pub fn start(events: &EventBus, server: &ServerHandle, light: bool) -> thread::JoinHandle<()> {
+    let kinds: &[EventKind] = if light {
+        &[EventKind::TxAddedToMempool]
+    } else {
+        &[EventKind::BlockConnected, EventKind::TxAddedToMempool]
+    };
+    let received = events.subscribe(kinds);
+    let server = server.clone();
+    thread::spawn(move || {
+        while let Ok(event) = received.recv() {
+            // announce what piled up meanwhile in as few messages as possible
+            let mut block_hashes = Vec::new();
+            let mut tx_hashes = Vec::new();
+            for event in std::iter::once(event).chain(received.try_iter()) {
+                match event {
+                    Event::BlockConnected { hash, .. } => block_hashes.push(hash),
+                    Event::TxAddedToMempool { txid } => tx_hashes.push(txid),
+                    _ => {}
+                }
+            }
+            if !block_hashes.is_empty() {
+                server.broadcast(Message::NewBlockHashes(block_hashes));
+            }
+            if !tx_hashes.is_empty() {
+                server.broadcast(Message::NewTransactionHashes(tx_hashes));
+            }
+        }
+        info!("Relay thread exited");
+    })
+}
@@ -6,13 +6,19 @@ use mio::{self, net};
 use mio_extras::channel;
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
 const MAX_INCOMING_CLIENT: usize = 256;
 const MAX_EVENT: usize = 1024;
+/// Time a peer has to complete the version handshake before it is disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest wait for events, so that stalled handshakes are noticed
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub fn new(
     addr: std::net::SocketAddr,
     msg_sink: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    disconnect_sink: cbchannel::Sender<std::net::SocketAddr>,
 ) -> std::io::Result<(Context, Handle)> {
     let (control_signal_sender, control_signal_receiver) = channel::channel();
     let handle = Handle {
@@ -25,6 +31,7 @@ pub fn new(
         poll: mio::Poll::new()?,
         control_chan: control_signal_receiver,
         new_msg_chan: msg_sink,
+        disconnect_chan: disconnect_sink,
         _handle: handle.clone(),
     };
     Ok((ctx, handle))
@@ -37,18 +44,21 @@ pub struct Context {
     poll: mio::Poll,
     control_chan: channel::Receiver<ControlSignal>,
     new_msg_chan: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    /// Receives the address of every peer that got disconnected
+    disconnect_chan: cbchannel::Sender<std::net::SocketAddr>,
     _handle: Handle,
 }
 
 impl Context {
-    /// Start a new server context.
-    pub fn start(mut self) -> std::io::Result<()> {
-        thread::spawn(move || {
+    /// Start a new server context. The returned thread ends when the server is shut down, which
+    /// closes the channel to the workers.
+    pub fn start(mut self) -> std::io::Result<thread::JoinHandle<()>> {
+        let handle = thread::spawn(move || {
             self.listen().unwrap_or_else(|e| {
                 error!("P2P server error: {}", e);
             });
         });
-        Ok(())
+        Ok(handle)
     }
 
     /// Register a TCP stream in the event loop, and initialize peer context.
@@ -133,14 +143,49 @@ impl Context {
             }
             ControlSignal::BroadcastMessage(msg) => {
                 trace!("Processing BroadcastMessage command");
+                // peers still shaking hands would ignore it
                 for peer_id in &self.peer_list {
-                    self.peers[*peer_id].handle.write(msg.clone());
+                    let handle = &self.peers[*peer_id].handle;
+                    if handle.is_established() {
+                        handle.write(msg.clone());
+                    }
+                }
+            }
+            ControlSignal::Shutdown => unreachable!(),
+            ControlSignal::DisconnectPeer(addr) => {
+                trace!("Processing DisconnectPeer command");
+                let peers = &self.peers;
+                if let Some(peer_id) = self.peer_list.iter().cloned().find(|&x| peers[x].addr == addr) {
+                    self.remove_peer(peer_id);
+                    info!("Disconnected peer {}", addr);
                 }
             }
         }
         Ok(())
     }
 
+    /// Disconnect the peers that have not completed the handshake in time.
+    fn drop_stalled_peers(&mut self) {
+        let peers = &self.peers;
+        let stalled: Vec<usize> = self.peer_list.iter()
+            .cloned()
+            .filter(|id| !peers[*id].handle.is_established() && peers[*id].connected.elapsed() > HANDSHAKE_TIMEOUT)
+            .collect();
+        for peer_id in stalled {
+            warn!("Peer {} did not complete the handshake, disconnecting", self.peers[peer_id].addr);
+            self.remove_peer(peer_id);
+        }
+    }
+
+    /// Drop a peer from the connection set and tell the workers it is gone.
+    fn remove_peer(&mut self, peer_id: usize) {
+        let peer = self.peers.remove(peer_id);
+        self.peer_list.retain(|&x| x != peer_id);
+        if self.disconnect_chan.send(peer.addr).is_err() {
+            debug!("No worker left to tell that peer {} disconnected", peer.addr);
+        }
+    }
+
     fn register_write_interest(&mut self, peer_id: usize) -> std::io::Result<()> {
         trace!("Registering socket write interest for peer {}", peer_id);
         let peer = &mut self.peers[peer_id];
@@ -164,9 +209,7 @@ impl Context {
                 Ok(ReadResult::EOF) => {
                     // EOF, remove it from the connections set
                     info!("Peer {} dropped connection", peer.addr);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.remove_peer(peer_id);
                     break;
                 }
                 Ok(ReadResult::Continue) => {
@@ -187,9 +230,7 @@ impl Context {
                         break;
                     } else {
                         warn!("Error reading peer {}, disconnecting: {}", peer.addr, e);
-                        self.peers.remove(peer_id);
-                        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                        self.peer_list.swap_remove(index);
+                        self.remove_peer(peer_id);
                         break;
                     }
                 }
@@ -224,9 +265,7 @@ impl Context {
             Ok(WriteResult::EOF) => {
                 // EOF, remove it from the connections set
                 info!("Peer {} dropped connection", peer.addr);
-                self.peers.remove(peer_id);
-                let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                self.peer_list.swap_remove(index);
+                self.remove_peer(peer_id);
             }
             Ok(WriteResult::ChanClosed) => {
                 // the channel is closed. no more writes.
@@ -246,9 +285,7 @@ impl Context {
                 // socket is not ready anymore, stop reading
                 } else {
                     warn!("Error writing peer {}, disconnecting: {}", peer.addr, e);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.remove_peer(peer_id);
                 }
             }
         }
@@ -284,7 +321,8 @@ impl Context {
         let mut events = mio::Events::with_capacity(MAX_EVENT);
 
         loop {
-            self.poll.poll(&mut events, None)?;
+            self.poll.poll(&mut events, Some(POLL_TIMEOUT))?;
+            self.drop_stalled_peers();
 
             for event in events.iter() {
                 match event.token() {
@@ -294,6 +332,12 @@ impl Context {
                         loop {
                             // get the new control singal from the channel
                             match self.control_chan.try_recv() {
+                                Ok(ControlSignal::Shutdown) => {
+                                    info!("P2P server shutting down, disconnecting all peers");
+                                    self.peers.clear();
+                                    self.peer_list.clear();
+                                    return Ok(());
+                                }
                                 Ok(req) => {
                                     self.process_control(req).unwrap();
                                 }
@@ -376,22 +420,38 @@ impl Handle {
             addr,
             result_chan: sender,
         };
+        let stopped = || std::io::Error::other("P2P server stopped");
         self.control_chan
             .send(ControlSignal::ConnectNewPeer(request))
-            .unwrap();
-        receiver.recv().unwrap()
+            .map_err(|_| stopped())?;
+        receiver.recv().map_err(|_| stopped())?
     }
 
     pub fn broadcast(&self, msg: message::Message) {
-        self.control_chan
-            .send(ControlSignal::BroadcastMessage(msg))
-            .unwrap();
+        if self.control_chan.send(ControlSignal::BroadcastMessage(msg)).is_err() {
+            debug!("P2P server stopped, dropping broadcast");
+        }
+    }
+
+    pub fn disconnect(&self, addr: std::net::SocketAddr) {
+        if self.control_chan.send(ControlSignal::DisconnectPeer(addr)).is_err() {
+            debug!("P2P server stopped, not disconnecting {}", addr);
+        }
+    }
+
+    /// Disconnect all peers and stop the server thread.
+    pub fn shutdown(&self) {
+        if self.control_chan.send(ControlSignal::Shutdown).is_err() {
+            debug!("P2P server already stopped");
+        }
     }
 }
 
 enum ControlSignal {
     ConnectNewPeer(ConnectRequest),
     BroadcastMessage(message::Message),
+    DisconnectPeer(std::net::SocketAddr),
+    Shutdown,
 }
 
 struct ConnectRequest {
@@ -1,67 +1,169 @@
-use super::message::Message;
+use super::message::{Handshake, Message};
 use super::peer;
 use crate::network::server::Handle as ServerHandle;
-use crossbeam::channel;
-use log::{debug, warn};
+use crossbeam::{channel, select};
+use log::{debug, info, warn};
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
+use crate::address::Address;
 use crate::block::Block;
-use crate::crypto::hash::{H160, H256, Hashable};
+use crate::crypto::hash::{H256, Hashable};
 use crate::blockchain::Blockchain;
 use crate::signedtrans::{SignedTrans};
-use crate::transaction::verify;
+use crate::crypto::sigcache::SignatureCache;
 use crate::mempool::Mempool;
+use crate::orphan::OrphanPool;
+use crate::events::{Event, EventBus, EventKind};
 
 use std::thread;
 use std::time::SystemTime;
 
+/// Most headers sent in one Headers message; a full batch means the light client should ask again
+const MAX_HEADERS: usize = 2000;
+
 #[derive(Clone)]
 pub struct Context {
     msg_chan: channel::Receiver<(Vec<u8>, peer::Handle)>,
+    /// Addresses of the peers the server disconnected
+    disconnect_chan: channel::Receiver<std::net::SocketAddr>,
     num_worker: usize,
     server: ServerHandle,
     bc: Arc<Mutex<Blockchain>>,
     mem_pool: Arc<Mutex<Mempool>>,
+    /// Signatures verified so far, shared by the worker threads
+    signatures: Arc<SignatureCache>,
+    /// Blocks waiting for their parent, shared by the worker threads
+    orphans: Arc<Mutex<OrphanPool>>,
+}
+
+/// What the worker threads share with the rest of the node
+#[derive(Clone)]
+pub struct Shared {
+    pub server: ServerHandle,
+    pub bc: Arc<Mutex<Blockchain>>,
+    pub mem_pool: Arc<Mutex<Mempool>>,
+    /// Signatures verified so far
+    pub signatures: Arc<SignatureCache>,
+    /// Blocks waiting for their parent
+    pub orphans: Arc<Mutex<OrphanPool>>,
 }
 
 pub fn new(
     num_worker: usize,
     msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
-    server: &ServerHandle,
-    bc: &Arc<Mutex<Blockchain>>,
-    mem_pool: &Arc<Mutex<Mempool>>
+    disconnect_src: channel::Receiver<std::net::SocketAddr>,
+    shared: &Shared,
 ) -> Context {
+    let shared = shared.clone();
     Context {
         msg_chan: msg_src,
+        disconnect_chan: disconnect_src,
         num_worker,
-        server: server.clone(),
-        bc: Arc::clone(bc),
-        mem_pool: Arc::clone(mem_pool),
+        server: shared.server,
+        bc: shared.bc,
+        mem_pool: shared.mem_pool,
+        signatures: shared.signatures,
+        orphans: shared.orphans,
+    }
+}
+
+/// Connect the orphans waiting for every block that joins the longest chain, whether it came
+/// from a peer, the local miner or the API. The thread exits once the bus is closed.
+pub fn adopt_orphans(events: &EventBus, shared: &Shared) -> thread::JoinHandle<()> {
+    let received = events.subscribe(&[EventKind::BlockConnected]);
+    let shared = shared.clone();
+    thread::spawn(move || {
+        while let Ok(event) = received.recv() {
+            if let Event::BlockConnected { hash, .. } = event {
+                let mut chain = shared.bc.lock().unwrap();
+                let mut orphans = shared.orphans.lock().unwrap();
+                for child in orphans.take_children(&hash) {
+                    insert_with_orphans(&mut chain, &mut orphans, &shared.mem_pool, &shared.signatures,
+                                        child, "the orphan pool");
+                }
+            }
+        }
+        info!("Orphan thread exited");
+    })
+}
+
+/// Insert `block`, then the orphans waiting for it or its descendants, skipping the invalid ones.
+fn insert_with_orphans(
+    chain: &mut Blockchain,
+    orphans: &mut OrphanPool,
+    mem_pool: &Mutex<Mempool>,
+    signatures: &SignatureCache,
+    block: Block,
+    source: &str,
+) {
+    let mut to_insert = vec![block];
+    while let Some(next_insert) = to_insert.pop() {
+        let valid = chain.validate(&next_insert)
+            .and_then(|_| chain.verify_transactions(&next_insert, signatures));
+        if let Err(e) = valid {
+            warn!("Invalid block {:?} from {}: {}", next_insert.hash(), source, e);
+            continue;
+        }
+        let mut pool = mem_pool.lock().unwrap();
+        for tx in next_insert.content.iter() {
+            pool.remove(tx);
+        }
+        drop(pool);
+        chain.insert(&next_insert);
+        to_insert.extend(orphans.take_children(&next_insert.hash()));
     }
 }
 
 impl Context {
-    pub fn start(self) {
+    /// Start the worker threads. They exit once the P2P server is stopped and every message it
+    /// already passed on has been handled.
+    pub fn start(self) -> Vec<thread::JoinHandle<()>> {
         let num_worker = self.num_worker;
+        let mut handles = Vec::new();
         for i in 0..num_worker {
             let cloned = self.clone();
-            thread::spawn(move || {
+            handles.push(thread::spawn(move || {
                 cloned.worker_loop();
-                warn!("Worker thread {} exited", i);
-            });
+                info!("Worker thread {} exited", i);
+            }));
         }
+        handles
     }
 
     fn worker_loop(&self) {
 
-        let mut memory:HashMap<H256,Block>= HashMap::new(); // parent's hash and dangling block
         let mut total_delay:u128 = 0;
         let mut reveived:u128 = 0;
 
         loop {
-            let msg = self.msg_chan.recv().unwrap();
+            let msg = select! {
+                recv(self.msg_chan) -> msg => match msg {
+                    Ok(msg) => msg,
+                    Err(_) => return,
+                },
+                recv(self.disconnect_chan) -> addr => {
+                    // its clock no longer counts
+                    if let Ok(addr) = addr {
+                        self.bc.lock().unwrap().remove_time_sample(addr.ip());
+                    }
+                    continue;
+                }
+            };
             let (msg, peer) = msg;
-            let msg: Message = bincode::deserialize(&msg).unwrap();
+            let msg: Message = match bincode::deserialize(&msg) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    warn!("Malformed message from peer {}, disconnecting: {}", peer.addr(), e);
+                    self.server.disconnect(peer.addr());
+                    continue;
+                }
+            };
+            // nothing counts before the peer has shown it is on our network
+            let handshake = matches!(msg, Message::Version(_) | Message::VersionAck(_));
+            if !handshake && !peer.is_established() {
+                debug!("Ignoring message from peer {} before the handshake", peer.addr());
+                continue;
+            }
             match msg {
                 Message::Ping(nonce) => {
                     debug!("Ping: {}", nonce);
@@ -72,6 +174,11 @@ impl Context {
                 }
                 //For NewBlockHashes, if the hashes are not already in blockchain, you need to ask for them by sending GetBlocks.
                 Message::NewBlockHashes(hashes) => {
+                    if self.bc.lock().unwrap().is_light() {
+                        let locator = self.bc.lock().unwrap().locator();
+                        peer.write(Message::GetHeaders(locator));
+                        continue;
+                    }
                     let mut dic: HashMap<H256, u32> = HashMap::new();
                     let blkchain =self.bc.lock().unwrap();
 
@@ -111,62 +218,39 @@ impl Context {
                 //for Blocks, insert the blocks into blockchain if not already in it
                 Message::Blocks(blocks)=>{
                     //don't find the parents of some blocks in #Block => #GetBlocks
-                    //broadcast #NewBlockhashes when received onr from #Block
-                    let mut dic_new: HashMap<H256, u32> = HashMap::new();
                     let mut dic_no_parent: HashMap<H256, u32> = HashMap::new();
+                    // verify the signatures in parallel, so that only cache lookups happen under the lock
+                    let checks: Vec<_> = {
+                        let bc = self.bc.lock().unwrap();
+                        blocks.iter().flat_map(|block| bc.signature_checks(block)).collect()
+                    };
+                    self.signatures.verify_batch(&checks);
                     let mut blkchain =self.bc.lock().unwrap();
                     let ts = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
 
-                    for block in blocks.iter() {
-                        if !blkchain.blocks.contains_key(&block.hash()){
-                            let new_block_parent = &block.header.parent;
-                            memory.insert(*new_block_parent,block.clone());
-                            total_delay += ts.as_millis() - block.header.get_create_time();
-                            reveived += 1;
-                            // PoW validity check
-                            if block.hash() <= block.header.difficulty {
-                                // Parent check
-                                if blkchain.blocks.contains_key(new_block_parent) {
-                                    if block.header.difficulty!= blkchain.blocks.get(new_block_parent).unwrap().0.header.difficulty {
-                                        continue;
-                                    }
-                                    let mut pool = self.mem_pool.lock().unwrap();
-                                    let signed_tx = block.content.clone();
-                                    for tx in signed_tx{
-                                        pool.remove(&tx);
-                                    }
-                                    // block.hash() < blkchain.blockchain.get(new_block_parent).unwrap().header.difficulty {
-                                    blkchain.insert(&block.clone());
-                                    memory.remove(&block.header.parent);
-                                    dic_new.insert(block.hash(), 1);
+                    let mut orphans = self.orphans.lock().unwrap();
 
-                                    // Orphan block handler: insert validated blocks stored in memory
-                                    let mut inserted: H256 = block.hash();
-                                    while memory.contains_key(&inserted) {
-                                        let next_insert = memory.get(&inserted).unwrap().clone();
-                                        let mut pool = self.mem_pool.lock().unwrap();
-                                        let signed_tx = block.content.clone();
-                                        for tx in signed_tx{
-                                            pool.remove(&tx);
-                                        }
-                                        blkchain.insert(&next_insert.clone());
-                                        memory.remove(&inserted);
-                                        inserted = next_insert.hash();
-                                        dic_new.insert(inserted, 1);
-                                    }
-                                } else {
-                                    dic_no_parent.insert(*new_block_parent, 1);
-                                }
-                            }
-                        }  
-                    }
-                    if dic_new.len()>0{
-                        let mut new_hashes: Vec<H256> = Vec::new();
-                        for item in dic_new {
-                            new_hashes.push(item.0);
+                    for block in blocks.iter() {
+                        if blkchain.blocks.contains_key(&block.hash()) || orphans.contains(&block.hash()) {
+                            continue;
+                        }
+                        total_delay += ts.as_millis().saturating_sub(block.header.get_create_time());
+                        reveived += 1;
+                        // PoW validity check
+                        if block.hash() > block.header.difficulty {
+                            continue;
                         }
-                        self.server.broadcast(Message::NewBlockHashes(new_hashes));
+                        // Parent check: keep the orphan and ask for its parent
+                        let new_block_parent = &block.header.parent;
+                        if !blkchain.blocks.contains_key(new_block_parent) {
+                            orphans.insert(block);
+                            dic_no_parent.insert(*new_block_parent, 1);
+                            continue;
+                        }
+                        insert_with_orphans(&mut blkchain, &mut orphans, &self.mem_pool, &self.signatures,
+                                            block.clone(), &format!("peer {}", peer.addr()));
                     }
+                    drop(orphans);
                     if dic_no_parent.len()>0{
                         let mut no_parents :Vec::<H256> = Vec::new();
                         for item in dic_no_parent {
@@ -214,40 +298,146 @@ impl Context {
                 }
 
                 Message::Transactions(txes) => {
-                    println!("received Transaction: {:?} trans {:?} to {:?}",
-                             H160::hash(&txes[0].public_key),
-                             txes[0].transaction.outputs[0].balance,
-                             txes[0].transaction.outputs[0].address);
-                    // println!("total block in chain {}",self.blkchain.lock().unwrap().get_num());
-                    let mem_pool = self.mem_pool.lock().unwrap().clone();
-                    let mut new_tx_hashes = Vec::new();
+                    if let Some(output) = txes.first().and_then(|tx| tx.transaction.outputs.first()) {
+                        let network = self.bc.lock().unwrap().params().network;
+                        debug!("Received {} transactions from peer {}, the first paying {} to {}",
+                               txes.len(), peer.addr(), output.balance, Address::new(network, output.address));
+                    }
+                    let checks: Vec<_> = {
+                        let chain = self.bc.lock().unwrap();
+                        txes.iter().flat_map(|tx| tx.signature_checks(|hash| chain.spent_outputs(hash))).collect()
+                    };
+                    self.signatures.verify_batch(&checks);
                     let mut chain = self.bc.lock().unwrap();
-                    // let mut pool = mem_pool.get_pool();
-                    let pool = mem_pool.pool.clone();
-                    for tx in txes{
-                        if !pool.contains_key(&tx.hash()){
-                            let pub_key = tx.get_public_key();
-                            let trans = tx.get_tx();
-                            let sig = tx.get_sig();
-                            let is_verified = verify(&trans, &pub_key, &sig);
-                            let is_over_spend = trans.output_val() > trans.input_val();
-                            if is_verified && !(is_over_spend) {
-                                let buf = tx.clone();
-                                self.mem_pool.lock().unwrap().pool.insert(tx.hash(), buf);
-                                new_tx_hashes.push(tx.hash());
-                                chain.update_state(&tx, self.mem_pool.lock().unwrap().clone().pool.len());
-                            }
+                    let mut pool = self.mem_pool.lock().unwrap();
+                    // the relay announces the accepted ones
+                    for tx in txes {
+                        if let Err(e) = pool.accept(&mut chain, &tx, &*self.signatures) {
+                            debug!("Rejected transaction {:?} from peer {}: {}", tx.hash(), peer.addr(), e);
                         }
                     }
-                    drop(chain);
-                    if !new_tx_hashes.is_empty() {
-                        self.server.broadcast(Message::NewTransactionHashes(new_tx_hashes));
-                    }
                 }
 
+                Message::Version(version) => {
+                    let mut bc = self.bc.lock().unwrap();
+                    if !version.is_compatible(&bc) {
+                        warn!("Peer {} is on a different network, disconnecting", peer.addr());
+                        self.server.disconnect(peer.addr());
+                        continue;
+                    }
+                    bc.add_time_sample(peer.addr().ip(), version.timestamp);
+                    // an incoming peer answers with its own version
+                    peer.send_version(Handshake::new(&bc));
+                    peer.write(Message::VersionAck(Handshake::new(&bc)));
+                    if peer.version_received() && bc.is_light() {
+                        peer.write(Message::GetHeaders(bc.locator()));
+                    }
+                }
+                Message::VersionAck(version) => {
+                    let mut bc = self.bc.lock().unwrap();
+                    if !version.is_compatible(&bc) {
+                        warn!("Peer {} is on a different network, disconnecting", peer.addr());
+                        self.server.disconnect(peer.addr());
+                        continue;
+                    }
+                    bc.add_time_sample(peer.addr().ip(), version.timestamp);
+                    if peer.ack_received() && bc.is_light() {
+                        peer.write(Message::GetHeaders(bc.locator()));
+                    }
+                }
+                Message::GetHeaders(locator) => {
+                    let bc = self.bc.lock().unwrap();
+                    if bc.is_light() {
+                        continue;
+                    }
+                    let headers = bc.headers_after(&locator, MAX_HEADERS);
+                    drop(bc);
+                    if !headers.is_empty() {
+                        peer.write(Message::Headers(headers));
+                    }
+                }
+                Message::Headers(headers) => {
+                    let mut bc = self.bc.lock().unwrap();
+                    let wallet = match bc.spv_wallet() {
+                        Some(wallet) => wallet.addresses(),
+                        None => continue,
+                    };
+                    let full_batch = headers.len() == MAX_HEADERS;
+                    let mut inserted = Vec::new();
+                    for header in headers.iter() {
+                        if bc.blocks.contains_key(&header.hash()) {
+                            continue;
+                        }
+                        if let Err(e) = bc.validate_header(header) {
+                            warn!("Invalid header from peer {}: {}", peer.addr(), e);
+                            break;
+                        }
+                        bc.insert_header(header);
+                        inserted.push(header.hash());
+                    }
+                    if full_batch {
+                        peer.write(Message::GetHeaders(bc.locator()));
+                    }
+                    // only the blocks whose filters match are worth asking about
+                    if !wallet.is_empty() && !inserted.is_empty() {
+                        peer.write(Message::GetFilters(inserted));
+                    }
+                }
+                Message::GetFilters(hashes) => {
+                    let bc = self.bc.lock().unwrap();
+                    let filters: Vec<_> = hashes.iter().filter_map(|hash| bc.filter(hash).cloned()).collect();
+                    drop(bc);
+                    if !filters.is_empty() {
+                        peer.write(Message::Filters(filters));
+                    }
+                }
+                Message::Filters(filters) => {
+                    let bc = self.bc.lock().unwrap();
+                    let wallet = match bc.spv_wallet() {
+                        Some(wallet) => wallet,
+                        None => continue,
+                    };
+                    if filters.iter().any(|filter| wallet.matches(filter)) {
+                        peer.write(Message::GetAddressTransactions(wallet.addresses()));
+                    }
+                }
+                Message::GetAddressTransactions(addresses) => {
+                    let bc = self.bc.lock().unwrap();
+                    if bc.is_light() {
+                        continue;
+                    }
+                    let found = bc.address_transactions(&addresses);
+                    drop(bc);
+                    if !found.is_empty() {
+                        peer.write(Message::AddressTransactions(found));
+                    }
+                }
+                Message::AddressTransactions(found) => {
+                    let mut bc = self.bc.lock().unwrap();
+                    for (tx, proof) in found.iter() {
+                        if let Err(e) = bc.add_proven_transaction(tx, proof) {
+                            warn!("Rejected transaction from peer {}: {}", peer.addr(), e);
+                        }
+                    }
+                }
+                Message::GetMerkleProof(txids) => {
+                    let bc = self.bc.lock().unwrap();
+                    let proofs: Vec<_> = txids.iter().filter_map(|txid| bc.tx_proof(txid)).collect();
+                    drop(bc);
+                    if !proofs.is_empty() {
+                        peer.write(Message::MerkleProof(proofs));
+                    }
+                }
+                Message::MerkleProof(proofs) => {
+                    for proof in proofs {
+                        debug!("Merkle proof of {:?} valid: {}", proof.txid, proof.verify());
+                    }
+                }
                 Message::Address(add)=>{
-                    println!("new address:{:?}", add);
                     let mut blockchain = self.bc.lock().unwrap();
+                    let network = blockchain.params().network;
+                    let shown: Vec<String> = add.iter().map(|a| Address::new(network, *a).to_string()).collect();
+                    println!("new address:{:?}", shown);
                     let mut newadd = vec![];
                     for address in add{
                         if !blockchain.address_list.contains(&address){
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::block::Block;
+use crate::clock::{Clock, SystemClock};
+use crate::crypto::hash::{H256, Hashable};
+
+/// Orphans kept at most; the oldest make room for new ones
+pub const MAX_ORPHANS: usize = 500;
+/// Milliseconds an orphan waits for its parent before it is dropped
+pub const ORPHAN_EXPIRY: u128 = 20 * 60 * 1000;
+
+/// Blocks received before their parent, shared by the worker threads and keyed by hash, so
+/// that siblings waiting for the same parent are all kept. Once the parent is inserted its
+/// children are taken out and inserted in turn.
+pub struct OrphanPool {
+    blocks: HashMap<H256, (Block, u128)>, // orphan and its arrival time
+    children: HashMap<H256, Vec<H256>>, // parent's hash and its orphan children
+    max_size: usize,
+    max_age: u128,
+    clock: Arc<dyn Clock>,
+}
+
+impl std::fmt::Debug for OrphanPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OrphanPool")
+            .field("orphans", &self.blocks.len())
+            .field("max_size", &self.max_size)
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::new(MAX_ORPHANS, ORPHAN_EXPIRY, Arc::new(SystemClock))
+    }
+}
+
+impl OrphanPool {
+    pub fn new(max_size: usize, max_age: u128, clock: Arc<dyn Clock>) -> Self {
+        OrphanPool { blocks: HashMap::new(), children: HashMap::new(), max_size, max_age, clock }
+    }
+
+    /// Keep a block until its parent arrives, returns false if it is already kept.
+    pub fn insert(&mut self, block: &Block) -> bool {
+        let hash = block.hash();
+        if self.blocks.contains_key(&hash) || self.max_size == 0 {
+            return false;
+        }
+        self.expire();
+        while self.blocks.len() >= self.max_size {
+            let oldest = self.blocks.iter()
+                .min_by_key(|(hash, (_, time))| (*time, **hash))
+                .map(|(hash, _)| *hash)
+                .unwrap();
+            self.remove(&oldest);
+        }
+        self.children.entry(block.header.parent).or_default().push(hash);
+        self.blocks.insert(hash, (block.clone(), self.clock.now()));
+        true
+    }
+
+    pub fn contains(&self, hash: &H256) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Take out the orphans whose parent is `parent`, in arrival order.
+    pub fn take_children(&mut self, parent: &H256) -> Vec<Block> {
+        self.children.remove(parent).unwrap_or_default().iter()
+            .filter_map(|hash| self.blocks.remove(hash))
+            .map(|(block, _)| block)
+            .collect()
+    }
+
+    /// Drop the orphans older than the maximum age, returns how many were dropped.
+    pub fn expire(&mut self) -> usize {
+        let now = self.clock.now();
+        let expired: Vec<H256> = self.blocks.iter()
+            .filter(|(_, (_, time))| now.saturating_sub(*time) > self.max_age)
+            .map(|(hash, _)| *hash)
+            .collect();
+        for hash in expired.iter() {
+            self.remove(hash);
+        }
+        expired.len()
+    }
+
+    fn remove(&mut self, hash: &H256) {
+        if let Some((block, _)) = self.blocks.remove(hash) {
+            let parent = block.header.parent;
+            if let Some(siblings) = self.children.get_mut(&parent) {
+                siblings.retain(|h| h != hash);
+                if siblings.is_empty() {
+                    self.children.remove(&parent);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::block::generate_random_block;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn siblings_are_kept() {
+        let mut pool = OrphanPool::default();
+        let parent: H256 = [1u8; 32].into();
+        let first = generate_random_block(&parent);
+        let second = generate_random_block(&parent);
+        let grandchild = generate_random_block(&first.hash());
+        assert!(pool.insert(&first));
+        assert!(pool.insert(&second));
+        assert!(!pool.insert(&first));
+        assert!(pool.insert(&grandchild));
+        assert_eq!(pool.len(), 3);
+
+        let children: Vec<H256> = pool.take_children(&parent).iter().map(|b| b.hash()).collect();
+        assert_eq!(children, vec![first.hash(), second.hash()]);
+        assert!(pool.take_children(&parent).is_empty());
+        assert_eq!(pool.take_children(&first.hash())[0].hash(), grandchild.hash());
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn expiry_and_eviction() {
+        let clock = Arc::new(MockClock::new(0));
+        let mut pool = OrphanPool::new(2, 1000, clock.clone());
+        let parent: H256 = [1u8; 32].into();
+        let old = generate_random_block(&parent);
+        pool.insert(&old);
+        clock.set(500);
+        let newer = generate_random_block(&parent);
+        pool.insert(&newer);
+        // full: the oldest goes
+        let newest = generate_random_block(&[2u8; 32].into());
+        pool.insert(&newest);
+        assert!(!pool.contains(&old.hash()));
+        assert!(pool.contains(&newer.hash()));
+        assert_eq!(pool.take_children(&parent).len(), 1);
+
+        clock.set(1_200);
+        assert_eq!(pool.expire(), 0);
+        clock.set(1_600);
+        assert_eq!(pool.expire(), 1);
+        assert!(pool.is_empty());
+        assert!(pool.take_children(&[2u8; 32].into()).is_empty());
+    }
+}
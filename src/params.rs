@@ -0,0 +1,207 @@
+use serde::{Serialize, Deserialize};
+use crate::block::Block;
+use crate::crypto::hash::H256;
+
+/// The chains a node can run on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl std::str::FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format!("unknown network {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Regtest => "regtest",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Name of the deployment that caps the coinbase of a block at the block subsidy
+pub const COINBASE_SUBSIDY: &str = "coinbase_subsidy";
+
+/// How a deployment of new validation rules activates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation {
+    /// Active from this height on
+    Height(u32),
+    /// Active from the window after the first window of `window` blocks (starting at height 0)
+    /// in which at least `threshold` blocks signal `bit`
+    Signal { bit: u8, window: u32, threshold: u32 },
+}
+
+/// A set of new validation rules and when the chain starts enforcing them.
+#[derive(Debug, Clone)]
+pub struct Deployment {
+    pub name: &'static str,
+    pub activation: Activation,
+}
+
+/// Consensus and networking constants of one chain.
+#[derive(Debug, Clone)]
+pub struct ChainParams {
+    pub network: Network,
+    /// Sent in the P2P handshake; peers with a different magic are on another chain
+    pub magic: [u8; 4],
+    pub genesis_difficulty: H256,
+    pub genesis_timestamp: u128,
+    /// Coinbase value of the first blocks, halved every `halving_interval` blocks
+    pub initial_subsidy: u8,
+    pub halving_interval: u32,
+    /// Maximum size of a serialized block in bytes
+    pub max_block_size: usize,
+    /// How far ahead of network-adjusted time a block timestamp may be, in milliseconds
+    pub max_future_drift: u128,
+    pub default_p2p_port: u16,
+    pub default_api_port: u16,
+    /// BIP44 coin type of wallet derivation paths
+    pub coin_type: u32,
+    /// Human-readable prefix of the network's addresses
+    pub address_hrp: &'static str,
+    pub deployments: Vec<Deployment>,
+}
+
+impl ChainParams {
+    pub fn mainnet() -> Self {
+        let mut difficulty = [0u8; 32];
+        difficulty[1] = 16;
+        ChainParams {
+            network: Network::Mainnet,
+            magic: [0xf9, 0xbe, 0xb4, 0xd9],
+            genesis_difficulty: difficulty.into(),
+            genesis_timestamp: 0,
+            initial_subsidy: 10,
+            halving_interval: 210_000,
+            max_block_size: 1_000_000,
+            max_future_drift: 2 * 60 * 60 * 1000,
+            default_p2p_port: 6000,
+            default_api_port: 7000,
+            coin_type: 0,
+            address_hrp: "ebc",
+            deployments: vec![Deployment {
+                name: COINBASE_SUBSIDY,
+                activation: Activation::Signal { bit: 0, window: 2016, threshold: 1916 },
+            }],
+        }
+    }
+
+    pub fn testnet() -> Self {
+        let mut difficulty = [0u8; 32];
+        difficulty[1] = 255;
+        ChainParams {
+            network: Network::Testnet,
+            magic: [0x0b, 0x11, 0x09, 0x07],
+            genesis_difficulty: difficulty.into(),
+            genesis_timestamp: 0,
+            initial_subsidy: 10,
+            halving_interval: 210_000,
+            max_block_size: 1_000_000,
+            max_future_drift: 2 * 60 * 60 * 1000,
+            default_p2p_port: 16000,
+            default_api_port: 17000,
+            coin_type: 1,
+            address_hrp: "tebc",
+            deployments: vec![Deployment {
+                name: COINBASE_SUBSIDY,
+                activation: Activation::Signal { bit: 0, window: 144, threshold: 108 },
+            }],
+        }
+    }
+
+    /// Private chain for tests: every hash meets the difficulty and the subsidy halves quickly.
+    pub fn regtest() -> Self {
+        ChainParams {
+            network: Network::Regtest,
+            magic: [0xfa, 0xbf, 0xb5, 0xda],
+            genesis_difficulty: [0xffu8; 32].into(),
+            genesis_timestamp: 0,
+            initial_subsidy: 10,
+            halving_interval: 150,
+            max_block_size: 1_000_000,
+            max_future_drift: 2 * 60 * 60 * 1000,
+            default_p2p_port: 26000,
+            default_api_port: 27000,
+            coin_type: 1,
+            address_hrp: "rebc",
+            deployments: vec![Deployment {
+                name: COINBASE_SUBSIDY,
+                activation: Activation::Signal { bit: 0, window: 144, threshold: 108 },
+            }],
+        }
+    }
+
+    pub fn for_network(network: Network) -> Self {
+        match network {
+            Network::Mainnet => Self::mainnet(),
+            Network::Testnet => Self::testnet(),
+            Network::Regtest => Self::regtest(),
+        }
+    }
+
+    /// The fixed first block of the chain.
+    pub fn genesis_block(&self) -> Block {
+        Block::builder()
+            .parent(H256::from([0u8; 32]))
+            .difficulty(self.genesis_difficulty)
+            .timestamp(self.genesis_timestamp)
+            .build()
+    }
+
+    pub fn deployment(&self, name: &str) -> Option<&Deployment> {
+        self.deployments.iter().find(|deployment| deployment.name == name)
+    }
+
+    /// Coinbase value of the block at `height`.
+    pub fn subsidy(&self, height: u32) -> u8 {
+        let halvings = height / self.halving_interval;
+        if halvings >= 8 {
+            0
+        } else {
+            self.initial_subsidy >> halvings
+        }
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::Hashable;
+
+    #[test]
+    fn subsidy_halves() {
+        let params = ChainParams::regtest();
+        assert_eq!(params.subsidy(0), 10);
+        assert_eq!(params.subsidy(149), 10);
+        assert_eq!(params.subsidy(150), 5);
+        assert_eq!(params.subsidy(300), 2);
+        assert_eq!(params.subsidy(150 * 8), 0);
+    }
+
+    #[test]
+    fn networks_have_distinct_genesis() {
+        let mainnet = ChainParams::mainnet().genesis_block().hash();
+        let testnet = ChainParams::testnet().genesis_block().hash();
+        let regtest = ChainParams::regtest().genesis_block().hash();
+        assert_ne!(mainnet, testnet);
+        assert_ne!(mainnet, regtest);
+        assert_ne!(testnet, regtest);
+    }
+}
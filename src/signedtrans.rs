@@ -1,9 +1,14 @@
 use serde::{Serialize, Deserialize};
-use crate::transaction::{Transaction, generate_random_transaction, sign};
-use crate::crypto::hash::{H256, Hashable};
+use crate::crypto::batch::{SignatureCheck, SignatureVerifier};
+use crate::transaction::{Output, Transaction, generate_random_transaction, sign, verify, witness_checks};
+use crate::crypto::hash::{tagged_hash, tags, H256, Hashable};
+use crate::encoding::{Decode, Encode, Reader};
 use crate::crypto::key_pair;
-use ring::{digest, signature::KeyPair};
+use ring::signature::KeyPair;
 
+/// A transaction as relayed and confirmed. The input witnesses authorize the spends; the
+/// transaction-wide signature is optional and ignored, since nothing ties its key to the spent
+/// outputs, and kept only so that the encoding is unchanged.
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct SignedTrans {
     pub transaction: Transaction,
@@ -15,17 +20,41 @@ impl SignedTrans{
     pub fn get_tx(&self) -> Transaction{self.clone().transaction}
     pub fn get_sig(&self) -> Vec<u8>{self.clone().signature}
     pub fn get_public_key(&self) -> Vec<u8>{self.clone().public_key}
+
+    /// Every signature of the input witnesses.
+    pub fn signature_checks<F>(&self, spent: F) -> Vec<SignatureCheck>
+        where F: Fn(&H256) -> Option<(Vec<Output>, u32)> {
+        witness_checks(&self.transaction, self.hash(), spent)
+    }
+
+    /// Check the spending conditions, as `transaction::verify` does.
+    pub fn verify<F>(&self, spent: F, height: u32, verifier: &dyn SignatureVerifier) -> Result<(), String>
+        where F: Fn(&H256) -> Option<(Vec<Output>, u32)> {
+        verify(&self.transaction, self.hash(), spent, height, verifier)
+    }
+}
+
+/// Encoded as the transaction, the signature, then the public key
+impl Encode for SignedTrans {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.transaction.encode_to(out);
+        self.signature.encode_to(out);
+        self.public_key.encode_to(out);
+    }
+}
+
+impl Decode for SignedTrans {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let transaction = Transaction::decode_from(reader)?;
+        let signature = Vec::<u8>::decode_from(reader)?;
+        let public_key = Vec::<u8>::decode_from(reader)?;
+        Ok(SignedTrans { transaction, signature, public_key })
+    }
 }
 
 impl Hashable for SignedTrans {
     fn hash(&self) -> H256 {
-        //unimplemented!()
-        let encoded: Vec<u8> = bincode::serialize(&self).unwrap();
-        let mut cat = digest::Context::new(&digest::SHA256);
-        cat.update(&encoded);
-        let fin = cat.finish();
-        let val = <H256>::from(fin);
-        val
+        tagged_hash(tags::TRANSACTION, &self.encode())
     }
 }
 
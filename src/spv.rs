@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+use crate::block::Block;
+use crate::blockchain::TxProof;
+use crate::filter::{address_item, outpoint_item, BlockFilter};
+use crate::crypto::hash::{H160, H256, Hashable};
+use crate::signedtrans::SignedTrans;
+use crate::transaction::{balance, Transaction};
+
+/// Wallet side of a light client. It only knows the transactions that touch its own addresses,
+/// each one proven against a header of the (headers-only) chain.
+#[derive(Debug, Clone, Default)]
+pub struct SpvWallet {
+    addresses: HashSet<H160>,
+    /// Proven transactions and the hash of the block including them
+    transactions: HashMap<H256, (SignedTrans, H256)>,
+}
+
+impl SpvWallet {
+    pub fn new(addresses: &[H160]) -> Self {
+        SpvWallet {
+            addresses: addresses.iter().cloned().collect(),
+            transactions: HashMap::new(),
+        }
+    }
+
+    pub fn addresses(&self) -> Vec<H160> {
+        self.addresses.iter().cloned().collect()
+    }
+
+    /// Whether a block may touch the wallet according to its compact filter: it pays one of our
+    /// addresses or spends one of our transactions.
+    pub fn matches(&self, filter: &BlockFilter) -> bool {
+        let items: Vec<Vec<u8>> = self.addresses.iter()
+            .map(address_item)
+            .chain(self.transactions.values().map(|(tx, _)| outpoint_item(&tx.transaction.id)))
+            .collect();
+        filter.match_any(&items)
+    }
+
+    /// Whether a transaction pays to one of our addresses or spends one of our transactions.
+    fn is_relevant(&self, tx: &Transaction) -> bool {
+        if tx.outputs.iter().any(|out| self.addresses.contains(&out.address)) {
+            return true;
+        }
+        let ours: HashSet<H256> = self.transactions.values().map(|(t, _)| t.transaction.id).collect();
+        tx.inputs.iter().any(|input| ours.contains(&input.previous_hash))
+    }
+
+    /// Accept a transaction sent by a full peer, if its proof checks out against a header we
+    /// already have.
+    pub fn add_proven(&mut self, headers: &HashMap<H256, (Block, u32)>, tx: &SignedTrans, proof: &TxProof) -> Result<(), String> {
+        let block_hash = proof.header.hash();
+        if !headers.contains_key(&block_hash) {
+            return Err(format!("unknown block {:?}", block_hash));
+        }
+        if tx.hash() != proof.txid || !proof.verify() {
+            return Err(format!("invalid merkle proof for {:?}", proof.txid));
+        }
+        if !self.is_relevant(&tx.transaction) {
+            return Err(format!("transaction {:?} does not touch our addresses", proof.txid));
+        }
+        self.transactions.insert(proof.txid, (tx.clone(), block_hash));
+        Ok(())
+    }
+
+    /// Balance of `address`, counting only the transactions included in blocks for which
+    /// `on_main_chain` holds.
+    pub fn balance<F>(&self, address: &H160, on_main_chain: F) -> u64 where F: Fn(&H256) -> bool {
+        let txs: Vec<&Transaction> = self.transactions.values()
+            .filter(|(_, block)| on_main_chain(block))
+            .map(|(tx, _)| &tx.transaction)
+            .collect();
+        balance(&txs, address)
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use crate::mempool::Mempool;
+    use crate::miner::generate_blocks;
+    use crate::params::ChainParams;
+
+    #[test]
+    fn accepts_only_proven_transactions() {
+        let address: H160 = [3u8; 20].into();
+        let mut full = Blockchain::with_params(ChainParams::regtest());
+        let blocks = generate_blocks(&mut full, &mut Mempool::new(), 2, &address);
+        let coinbase = blocks[0].content[0].clone();
+        let proof = full.tx_proof(&coinbase.hash()).unwrap();
+
+        let mut light = Blockchain::with_params(ChainParams::regtest());
+        let mut wallet = SpvWallet::new(&[address]);
+        // the header is not known yet
+        assert!(wallet.add_proven(&light.blocks, &coinbase, &proof).is_err());
+        for block in blocks.iter() {
+            light.insert_header(&block.header);
+        }
+        // the proof belongs to another transaction
+        let other = blocks[1].content[0].clone();
+        assert!(wallet.add_proven(&light.blocks, &other, &proof).is_err());
+        wallet.add_proven(&light.blocks, &coinbase, &proof).unwrap();
+        assert_eq!(wallet.balance(&address, |h| light.contain(*h)), 10);
+
+        // nobody else's transactions are accepted
+        let mut stranger = SpvWallet::new(&[[4u8; 20].into()]);
+        assert!(stranger.add_proven(&light.blocks, &coinbase, &proof).is_err());
+    }
+}
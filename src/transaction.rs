@@ -1,13 +1,18 @@
 use serde::{Serialize, Deserialize};
 use rand::Rng;
 use std::collections::HashSet;
-use ring::{digest, rand::SecureRandom, signature::Ed25519KeyPair};
-use crate::crypto::hash::{H256,H160,Hashable, generate_rand_hash256,generate_rand_hash160};
+use ring::{digest, rand::SecureRandom, signature::{Ed25519KeyPair, KeyPair}};
+use crate::crypto::hash::{tagged_hash, tags, H256,H160,Hashable, generate_rand_hash256,generate_rand_hash160};
+use crate::encoding::{encode_varint, Decode, Encode, Reader};
+use crate::crypto::batch::{SignatureCheck, SignatureVerifier};
+use crate::condition::{Condition, SigHashType, SpendContext, WitnessSignature};
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Input {
     pub index: u8,
     pub previous_hash: H256,
+    /// Signatures satisfying the conditions of the spent outputs; not covered by signatures
+    pub witness: Vec<WitnessSignature>,
 }
 
 impl Input{
@@ -15,14 +20,27 @@ impl Input{
     pub fn get_hash(&self) -> H256 {self.clone().previous_hash}
 }
 
+/// Encoded as the previous hash, the index, then the witness
+impl Encode for Input {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.previous_hash.encode_to(out);
+        self.index.encode_to(out);
+        self.witness.encode_to(out);
+    }
+}
+
+impl Decode for Input {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let previous_hash = H256::decode_from(reader)?;
+        let index = u8::decode_from(reader)?;
+        let witness = Vec::<WitnessSignature>::decode_from(reader)?;
+        Ok(Input { index, previous_hash, witness })
+    }
+}
+
 impl Hashable for Input {
     fn hash(&self) -> H256 {
-        let encoded: Vec<u8> = bincode::serialize(&self).unwrap();
-        let mut cat = digest::Context::new(&digest::SHA256);
-        cat.update(&encoded);
-        let fin = cat.finish();
-        let val = <H256>::from(fin);
-        val
+        digest::digest(&digest::SHA256, &self.encode()).into()
     }
 }
 
@@ -31,7 +49,8 @@ impl Hashable for Input {
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Output {
     pub balance: u8,
-    pub address: H160
+    pub address: H160,
+    pub condition: Condition,
 }
 
 impl Output{
@@ -39,14 +58,27 @@ impl Output{
     pub fn get_address(&self) -> H160 {self.clone().address}
 }
 
+/// Encoded as the address, the balance, then the condition
+impl Encode for Output {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.address.encode_to(out);
+        self.balance.encode_to(out);
+        self.condition.encode_to(out);
+    }
+}
+
+impl Decode for Output {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let address = H160::decode_from(reader)?;
+        let balance = u8::decode_from(reader)?;
+        let condition = Condition::decode_from(reader)?;
+        Ok(Output { balance, address, condition })
+    }
+}
+
 impl Hashable for Output{
     fn hash(&self) -> H256 {
-        let encoded: Vec<u8> = bincode::serialize(&self).unwrap();
-        let mut cat = digest::Context::new(&digest::SHA256);
-        cat.update(&encoded);
-        let fin = cat.finish();
-        let val = <H256>::from(fin);
-        val
+        digest::digest(&digest::SHA256, &self.encode()).into()
     }
 }
 
@@ -59,6 +91,24 @@ pub struct Transaction {
     pub outputs: Vec<Output>
 }
 
+/// Encoded as the id, the inputs, then the outputs
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.id.encode_to(out);
+        self.inputs.encode_to(out);
+        self.outputs.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(reader: &mut Reader) -> Result<Self, String> {
+        let id = H256::decode_from(reader)?;
+        let inputs = Vec::<Input>::decode_from(reader)?;
+        let outputs = Vec::<Output>::decode_from(reader)?;
+        Ok(Transaction { id, inputs, outputs })
+    }
+}
+
 impl Transaction{
     pub fn get_id(&self) -> H256{self.clone().id}
     pub fn get_input(&self) -> Vec<Input>{self.clone().inputs}
@@ -79,31 +129,178 @@ impl Transaction{
     pub fn output_val(&self) -> u8 {
         self.outputs.iter().map(|output| output.balance).sum()
     }
+
+    /// Hash of the transaction without its witnesses, which the transaction-wide signature signs
+    pub fn unwitnessed_hash(&self) -> H256 {
+        let mut stripped = self.clone();
+        for input in stripped.inputs.iter_mut() {
+            input.witness.clear();
+        }
+        digest::digest(&digest::SHA256, &stripped.encode()).into()
+    }
+
+    /// Digest a witness signature of input `index` signs: the sighash type, the id, the inputs
+    /// (only this one with `ANYONECANPAY`), the outputs selected by the base type, the position
+    /// of the input and the outputs it spends, amounts and owners included.
+    pub fn sighash(&self, index: usize, spent: &[Output], sighash_type: SigHashType) -> Result<H256, String> {
+        let input = self.inputs.get(index)
+            .ok_or_else(|| format!("no input {} to sign", index))?;
+        SigHashType::from_byte(sighash_type.as_byte())?;
+        let mut data = Vec::new();
+        sighash_type.as_byte().encode_to(&mut data);
+        self.id.encode_to(&mut data);
+        let inputs: Vec<&Input> = if sighash_type.is_anyone_can_pay() {
+            vec![input]
+        } else {
+            self.inputs.iter().collect()
+        };
+        encode_varint(inputs.len() as u64, &mut data);
+        for input in inputs {
+            input.previous_hash.encode_to(&mut data);
+            input.index.encode_to(&mut data);
+        }
+        let outputs: &[Output] = match sighash_type.base() {
+            SigHashType::NONE => &[],
+            SigHashType::SINGLE => std::slice::from_ref(self.outputs.get(index)
+                .ok_or_else(|| format!("SINGLE signature of input {} without matching output", index))?),
+            _ => &self.outputs,
+        };
+        encode_varint(outputs.len() as u64, &mut data);
+        for output in outputs {
+            output.encode_to(&mut data);
+        }
+        if !sighash_type.is_anyone_can_pay() {
+            (index as u32).encode_to(&mut data);
+        }
+        encode_varint(spent.len() as u64, &mut data);
+        for output in spent {
+            output.encode_to(&mut data);
+        }
+        Ok(tagged_hash(tags::SIGHASH, &data))
+    }
+
+    /// Whether this is a coinbase, spending nothing but the null input
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1 && self.inputs[0].previous_hash == H256::from([0xffu8; 32])
+    }
 }
 
 /// Create digital signature of a transaction
 pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Vec<u8> {
-    let serialized = bincode::serialize(&t).unwrap();
-    let msg = digest::digest(&digest::SHA256, &serialized);
-    let sig = key.sign(msg.as_ref()).as_ref().to_vec();
-    return sig;
+    key.sign(t.unwitnessed_hash().as_ref()).as_ref().to_vec()
 }
 
 /// Verify digital signature of a transaction, using public key instead of secret key
-pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
-    let serialized = bincode::serialize(t).unwrap();
-    let msg = digest::digest(&digest::SHA256, &serialized);
-    let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key.as_ref());
-    peer_public_key.verify(msg.as_ref(), signature.as_ref()).is_ok()
+pub fn verify_signature(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
+    let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
+    peer_public_key.verify(t.unwitnessed_hash().as_ref(), signature).is_ok()
+}
+
+/// Sign input `index` of a transaction spending `spent`, for its witness.
+pub fn sign_input(t: &Transaction, index: usize, spent: &[Output], key: &Ed25519KeyPair, sighash_type: SigHashType)
+    -> Result<WitnessSignature, String> {
+    let sighash = t.sighash(index, spent, sighash_type)?;
+    Ok(WitnessSignature {
+        public_key: key.public_key().as_ref().to_vec(),
+        signature: key.sign(sighash.as_ref()).as_ref().to_vec(),
+        sighash_type,
+    })
+}
+
+/// Check the witness of every input against the conditions of the outputs it spends. Inputs spend
+/// whole transactions in this ledger, so a witness must satisfy every output of the spent
+/// transaction, and the value an input claims must be what those outputs hold. Outputs with a
+/// condition other than pay-to-address must pay the condition's address. `spent` finds the outputs
+/// of a transaction and the height of the block confirming it; `height` is the height of the block
+/// the transaction goes in. `txid` is the hash of the signed transaction, as `verifier` knows it.
+pub fn verify<F>(t: &Transaction, txid: H256, spent: F, height: u32, verifier: &dyn SignatureVerifier) -> Result<(), String>
+    where F: Fn(&H256) -> Option<(Vec<Output>, u32)> {
+    // otherwise anyone could credit coins they control to someone else's address
+    for output in t.outputs.iter() {
+        if output.condition != Condition::PayToAddress && output.address != output.condition.address() {
+            return Err(format!("output with a condition pays {} instead of {}", output.address, output.condition.address()));
+        }
+    }
+    if t.is_coinbase() {
+        return Ok(());
+    }
+    // the witnesses are what authorizes a transaction, so it must have some
+    if t.inputs.is_empty() {
+        return Err("transaction has no inputs".to_string());
+    }
+    for (index, input) in t.inputs.iter().enumerate() {
+        let (outputs, confirmed_height) = spent(&input.previous_hash)
+            .ok_or_else(|| format!("input spends unknown transaction {}", input.previous_hash))?;
+        let value: u64 = outputs.iter().map(|output| output.balance as u64).sum();
+        if input.index as u64 != value {
+            return Err(format!("input spending {} claims {}, its outputs hold {}", input.previous_hash, input.index, value));
+        }
+        let sighash = |sighash_type| t.sighash(index, &outputs, sighash_type);
+        for output in outputs.iter() {
+            let ctx = SpendContext {
+                txid,
+                sighash: &sighash,
+                verifier,
+                address: output.address,
+                confirmed_height,
+                spend_height: height,
+            };
+            output.condition.evaluate(&input.witness, &ctx)
+                .map_err(|e| format!("input spending {}: {}", input.previous_hash, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// The witness signatures of a transaction, with the messages they sign, to verify ahead of
+/// `verify`. Inputs spending unknown transactions and signatures of invalid types have nothing
+/// to check.
+pub fn witness_checks<F>(t: &Transaction, txid: H256, spent: F) -> Vec<SignatureCheck>
+    where F: Fn(&H256) -> Option<(Vec<Output>, u32)> {
+    if t.is_coinbase() {
+        return Vec::new();
+    }
+    let mut checks = Vec::new();
+    for (index, input) in t.inputs.iter().enumerate() {
+        let outputs = match spent(&input.previous_hash) {
+            Some((outputs, _)) => outputs,
+            None => continue,
+        };
+        for sig in input.witness.iter() {
+            if let Ok(message) = t.sighash(index, &outputs, sig.sighash_type) {
+                checks.push(SignatureCheck {
+                    txid,
+                    public_key: sig.public_key.clone(),
+                    message,
+                    signature: sig.signature.clone(),
+                });
+            }
+        }
+    }
+    checks
+}
+
+/// Create the coinbase transaction of the block at `height`, paying `value` to `address`. The id is
+/// derived from the height and the payout address, so the same block always carries the same coinbase.
+pub fn coin_base(address: &H160, value: u8, height: u32) -> Transaction{
+    let input = Input{index: 0, previous_hash: H256::from([0xffu8; 32]), witness: Vec::new()};
+    let output = Output{ balance: value,  address: *address, condition: Condition::PayToAddress};
+    let data = [&height.to_be_bytes()[..], address.as_ref()].concat();
+    let id = tagged_hash(tags::COINBASE, &data);
+    Transaction{id, inputs: vec![input], outputs: vec![output]}
 }
 
-pub fn coin_base(address: &H160) -> Transaction{
-    use hex_literal::hex;
-    let hash = (hex!("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")).into();
-    let input = Input{index: 0, previous_hash: hash};
-    let output = Output{ balance: 10,  address: address.clone()};
-    let t = Transaction{id:generate_rand_hash256(), inputs: vec![input], outputs: vec![output]};
-    t
+/// Sum of the outputs paying `address` in transactions that no transaction of `txs` spends.
+pub fn balance(txs: &[&Transaction], address: &H160) -> u64 {
+    let spent: HashSet<H256> = txs.iter()
+        .flat_map(|tx| tx.inputs.iter().map(|input| input.previous_hash))
+        .collect();
+    txs.iter()
+        .filter(|tx| !spent.contains(&tx.id))
+        .flat_map(|tx| tx.outputs.iter())
+        .filter(|output| output.address == *address)
+        .map(|output| output.balance as u64)
+        .sum()
 }
 
 pub fn generate_random_transaction() -> Transaction {
@@ -113,10 +310,10 @@ pub fn generate_random_transaction() -> Transaction {
     sr.fill(&mut result).unwrap();
     let hash:H256 = generate_rand_hash256();
     let index:u8 = rng.gen();
-    let inputs = Input{index, previous_hash:hash};
+    let inputs = Input{index, previous_hash:hash, witness: Vec::new()};
     let val:u8 = rng.gen();
     let address = generate_rand_hash160();
-    let outputs = Output{ balance: val, address};
+    let outputs = Output{ balance: val, address, condition: Condition::PayToAddress};
     let id = generate_rand_hash256();
     let trans = Transaction{id, inputs:vec![inputs], outputs:vec![outputs] };
     trans
@@ -124,8 +321,8 @@ pub fn generate_random_transaction() -> Transaction {
 
 #[cfg(any(test, test_utilities))]
 mod tests {
-    use ring::signature::KeyPair;
     use super::*;
+    use crate::crypto::batch::DirectVerifier;
     use crate::crypto::key_pair;
 
     #[test]
@@ -133,6 +330,106 @@ mod tests {
         let t = generate_random_transaction();
         let key = key_pair::random();
         let signature = sign(&t, &key);
-        assert!(verify(&t, &(key.public_key().as_ref()), &signature));
+        assert!(verify_signature(&t, key.public_key().as_ref(), &signature));
+    }
+
+    #[test]
+    fn verify_witnesses() {
+        let key = key_pair::random();
+        let address = H160::hash(key.public_key().as_ref());
+        let funding_id = generate_rand_hash256();
+        let funding = vec![Output { balance: 5, address, condition: Condition::PayToAddress }];
+        let spent = |id: &H256| if *id == funding_id { Some((funding.clone(), 1)) } else { None };
+
+        let mut t = Transaction {
+            id: generate_rand_hash256(),
+            inputs: vec![Input { index: 5, previous_hash: funding_id, witness: Vec::new() }],
+            outputs: vec![Output { balance: 5, address: generate_rand_hash160(), condition: Condition::PayToAddress }],
+        };
+        assert!(verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).is_err());
+        let witness = sign_input(&t, 0, &funding, &key, SigHashType::ALL).unwrap();
+        t.inputs[0].witness.push(witness);
+        verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).unwrap();
+
+        // an input cannot claim more than the outputs it spends hold, even when signed
+        let mut inflated = t.clone();
+        inflated.inputs[0].index = 200;
+        inflated.inputs[0].witness = vec![sign_input(&inflated, 0, &funding, &key, SigHashType::ALL).unwrap()];
+        assert_eq!(verify(&inflated, inflated.unwitnessed_hash(), spent, 2, &DirectVerifier),
+                   Err(format!("input spending {} claims 200, its outputs hold 5", funding_id)));
+
+        // the witness does not cover a change of the outputs
+        t.outputs[0].balance = 4;
+        assert!(verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).is_err());
+        t.outputs[0].balance = 5;
+        t.inputs[0].previous_hash = generate_rand_hash256();
+        assert!(verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).is_err());
+        t.inputs.clear();
+        assert!(verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).is_err());
+    }
+
+    #[test]
+    fn condition_outputs_pay_their_address() {
+        let multisig = Condition::Multisig { threshold: 1, keys: vec![key_pair::random().public_key().as_ref().to_vec()] };
+        let victim = generate_rand_hash160();
+        let mut coinbase = coin_base(&victim, 10, 1);
+        verify(&coinbase, coinbase.id, |_| None, 1, &DirectVerifier).unwrap();
+        coinbase.outputs[0].condition = multisig.clone();
+        assert!(verify(&coinbase, coinbase.id, |_| None, 1, &DirectVerifier).is_err());
+        coinbase.outputs[0].address = multisig.address();
+        verify(&coinbase, coinbase.id, |_| None, 1, &DirectVerifier).unwrap();
+    }
+
+    #[test]
+    fn inputs_of_several_owners() {
+        let keys = [key_pair::random(), key_pair::random()];
+        let funding: Vec<(H256, Vec<Output>)> = keys.iter().map(|key| {
+            let address = H160::hash(key.public_key().as_ref());
+            (generate_rand_hash256(), vec![Output { balance: 3, address, condition: Condition::PayToAddress }])
+        }).collect();
+        let spent = |id: &H256| funding.iter().find(|(hash, _)| hash == id).map(|(_, outputs)| (outputs.clone(), 1));
+
+        let mut t = Transaction {
+            id: generate_rand_hash256(),
+            inputs: funding.iter().map(|(hash, _)| Input { index: 3, previous_hash: *hash, witness: Vec::new() }).collect(),
+            outputs: vec![Output { balance: 6, address: generate_rand_hash160(), condition: Condition::PayToAddress }],
+        };
+        for (index, key) in keys.iter().enumerate() {
+            let witness = sign_input(&t, index, &funding[index].1, key, SigHashType::ALL).unwrap();
+            t.inputs[index].witness.push(witness);
+        }
+        verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).unwrap();
+
+        // a signature does not carry over to another input
+        t.inputs.swap(0, 1);
+        assert!(verify(&t, t.unwitnessed_hash(), spent, 2, &DirectVerifier).is_err());
+    }
+
+    #[test]
+    fn sighash_coverage() {
+        let key = key_pair::random();
+        let spent = vec![Output { balance: 3, address: H160::hash(key.public_key().as_ref()), condition: Condition::PayToAddress }];
+        let mut t = generate_random_transaction();
+        let digest = |t: &Transaction, sighash_type| t.sighash(0, &spent, sighash_type).unwrap();
+        let all = digest(&t, SigHashType::ALL);
+        let none = digest(&t, SigHashType::NONE);
+
+        // NONE does not cover the outputs
+        t.outputs[0].address = generate_rand_hash160();
+        assert_ne!(digest(&t, SigHashType::ALL), all);
+        assert_eq!(digest(&t, SigHashType::NONE), none);
+
+        // ANYONECANPAY does not cover the other inputs, SINGLE the other outputs
+        let single_acp = digest(&t, SigHashType::SINGLE.anyone_can_pay());
+        let mut joined = t.clone();
+        joined.inputs.push(Input { index: 9, previous_hash: generate_rand_hash256(), witness: Vec::new() });
+        joined.outputs.push(Output::default());
+        assert_eq!(digest(&joined, SigHashType::SINGLE.anyone_can_pay()), single_acp);
+        assert_ne!(digest(&joined, SigHashType::SINGLE), digest(&t, SigHashType::SINGLE));
+
+        // the spent outputs are covered
+        let other = vec![Output { balance: 4, ..spent[0].clone() }];
+        assert_ne!(t.sighash(0, &other, SigHashType::NONE).unwrap(), digest(&t, SigHashType::NONE));
+        assert!(t.sighash(1, &spent, SigHashType::ALL).is_err());
     }
 }
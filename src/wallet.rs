@@ -0,0 +1,306 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use ring::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize};
+use crate::condition::{Condition, SigHashType};
+use crate::crypto::hash::{generate_rand_hash256, H160};
+use crate::crypto::hd::{ExtendedKey, HARDENED};
+use crate::crypto::mnemonic::Mnemonic;
+use crate::params::{ChainParams, Network};
+use crate::signedtrans::SignedTrans;
+use crate::transaction::{sign_input, Input, Output, Transaction};
+
+/// Words of the phrase of a new wallet
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// Receive or change chain of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Receive = 0,
+    Change = 1,
+}
+
+/// Keys derived from a mnemonic seed along `m/44'/coin'/account'/chain'/index'`, every level
+/// hardened as Ed25519 requires. Restoring the phrase restores the keys; addresses are handed
+/// out in order, so the next ones to scan for are the ones after the last used.
+pub struct HdWallet {
+    mnemonic: Mnemonic,
+    account: ExtendedKey,
+    /// Next index of the receive and change chains
+    next: [u32; 2],
+    keys: HashMap<H160, ExtendedKey>,
+}
+
+impl HdWallet {
+    /// A wallet with a fresh phrase.
+    pub fn generate(coin_type: u32) -> Self {
+        Self::from_mnemonic(Mnemonic::generate(MNEMONIC_WORDS).unwrap(), "", coin_type)
+    }
+
+    /// The first account of the seed of `mnemonic` and `passphrase`.
+    pub fn from_mnemonic(mnemonic: Mnemonic, passphrase: &str, coin_type: u32) -> Self {
+        let master = ExtendedKey::master(&mnemonic.to_seed(passphrase));
+        let account = master.derive(&format!("m/44'/{}'/0'", coin_type)).unwrap();
+        HdWallet { mnemonic, account, next: [0, 0], keys: HashMap::new() }
+    }
+
+    /// The wallet of `from_mnemonic` with the addresses below `next` already handed out, as
+    /// returned by `next_indices`.
+    pub fn restore(mnemonic: Mnemonic, passphrase: &str, coin_type: u32, next: [u32; 2]) -> Self {
+        let mut wallet = Self::from_mnemonic(mnemonic, passphrase, coin_type);
+        for _ in 0..next[Chain::Receive as usize] {
+            wallet.receive_address();
+        }
+        for _ in 0..next[Chain::Change as usize] {
+            wallet.change_address();
+        }
+        wallet
+    }
+
+    /// Next index of the receive and change chains.
+    pub fn next_indices(&self) -> [u32; 2] {
+        self.next
+    }
+
+    /// The phrase to back the wallet up with.
+    pub fn mnemonic(&self) -> &Mnemonic {
+        &self.mnemonic
+    }
+
+    /// Hash of the first receive address, which tells whether a passphrase gives back this wallet.
+    pub fn fingerprint(&self) -> H160 {
+        let key = self.account.child(HARDENED + Chain::Receive as u32)
+            .and_then(|chain| chain.child(HARDENED))
+            .unwrap();
+        H160::hash(H160::hash(&key.public_key()).as_ref())
+    }
+
+    /// A fresh address to be paid to.
+    pub fn receive_address(&mut self) -> H160 {
+        self.next_address(Chain::Receive)
+    }
+
+    /// A fresh address for the change of our own transactions.
+    pub fn change_address(&mut self) -> H160 {
+        self.next_address(Chain::Change)
+    }
+
+    /// The key of an address handed out by this wallet.
+    pub fn key_pair(&self, address: &H160) -> Option<Ed25519KeyPair> {
+        self.keys.get(address).map(ExtendedKey::key_pair)
+    }
+
+    /// Addresses handed out so far.
+    pub fn addresses(&self) -> Vec<H160> {
+        self.keys.keys().cloned().collect()
+    }
+
+    /// A signed transaction paying `amount` to `to` out of `unspent`, the transactions paying
+    /// this wallet, with the rest going to a fresh change address. Inputs spend whole
+    /// transactions, so only those whose outputs all pay this wallet are used.
+    pub fn create_transaction(&mut self, unspent: &[Transaction], to: H160, amount: u8) -> Result<SignedTrans, String> {
+        let mut inputs = Vec::new();
+        let mut spent: Vec<&[Output]> = Vec::new();
+        let mut total: u8 = 0;
+        for tx in unspent.iter() {
+            if total >= amount {
+                break;
+            }
+            let ours = tx.outputs.iter()
+                .all(|out| self.keys.contains_key(&out.address) && out.condition == Condition::PayToAddress);
+            let value = tx.outputs.iter().try_fold(0u8, |sum, out| sum.checked_add(out.balance));
+            if let (true, Some(value)) = (ours, value.and_then(|value| total.checked_add(value))) {
+                inputs.push(Input { index: value - total, previous_hash: tx.id, witness: Vec::new() });
+                spent.push(&tx.outputs);
+                total = value;
+            }
+        }
+        if total < amount {
+            return Err(format!("insufficient funds: {} spendable, {} needed", total, amount));
+        }
+        let mut outputs = vec![Output { balance: amount, address: to, condition: Condition::PayToAddress }];
+        if total > amount {
+            let change = self.change_address();
+            outputs.push(Output { balance: total - amount, address: change, condition: Condition::PayToAddress });
+        }
+        let mut transaction = Transaction { id: generate_rand_hash256(), inputs, outputs };
+
+        let mut witnesses = Vec::new();
+        for (index, outputs) in spent.iter().enumerate() {
+            let addresses: HashSet<H160> = outputs.iter().map(|out| out.address).collect();
+            let witness = addresses.iter()
+                .map(|address| sign_input(&transaction, index, outputs, &self.key_pair(address).unwrap(), SigHashType::ALL))
+                .collect::<Result<Vec<_>, String>>()?;
+            witnesses.push(witness);
+        }
+        for (input, witness) in transaction.inputs.iter_mut().zip(witnesses) {
+            input.witness = witness;
+        }
+        // the witnesses authorize the spends, there is no transaction-wide signature
+        Ok(SignedTrans { transaction, signature: Vec::new(), public_key: Vec::new() })
+    }
+
+    fn next_address(&mut self, chain: Chain) -> H160 {
+        let index = self.next[chain as usize];
+        self.next[chain as usize] += 1;
+        let key = self.account.child(HARDENED + chain as u32)
+            .and_then(|chain| chain.child(HARDENED + index))
+            .unwrap();
+        let address = H160::hash(&key.public_key());
+        self.keys.insert(address, key);
+        address
+    }
+}
+
+/// What a keystore file holds: enough to rederive every key handed out, together with the
+/// passphrase, which is never written down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    pub network: Network,
+    pub mnemonic: String,
+    /// Whether the seed is protected by a passphrase, which has to be asked for
+    pub protected: bool,
+    /// `HdWallet::fingerprint` of the seed, to reject a mistyped passphrase
+    pub fingerprint: H160,
+    /// Next index of the receive and change chains
+    pub next: [u32; 2],
+}
+
+impl Keystore {
+    pub fn new(network: Network, wallet: &HdWallet, protected: bool) -> Self {
+        Keystore {
+            network,
+            mnemonic: wallet.mnemonic().phrase(),
+            protected,
+            fingerprint: wallet.fingerprint(),
+            next: wallet.next_indices(),
+        }
+    }
+
+    /// Read the keystore at `path`, which must be for `network`.
+    pub fn load(path: &Path, network: Network) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("error reading keystore {}: {}", path.display(), e))?;
+        let keystore: Keystore = serde_json::from_str(&json)
+            .map_err(|e| format!("error parsing keystore {}: {}", path.display(), e))?;
+        if keystore.network != network {
+            return Err(format!("keystore {} is for {}, not {}", path.display(), keystore.network, network));
+        }
+        Ok(keystore)
+    }
+
+    /// Write the keystore, readable by its owner only since it holds the seed.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("error creating {}: {}", dir.display(), e))?;
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(path)
+            .and_then(|mut file| file.write_all(serde_json::to_string_pretty(self).unwrap().as_bytes()))
+            .map_err(|e| format!("error writing keystore {}: {}", path.display(), e))
+    }
+
+    /// The wallet of the keystore, with the same addresses handed out. Any other passphrase
+    /// than the one the keystore was created with is refused.
+    pub fn wallet(&self, passphrase: &str) -> Result<HdWallet, String> {
+        let coin_type = ChainParams::for_network(self.network).coin_type;
+        let wallet = HdWallet::restore(Mnemonic::parse(&self.mnemonic)?, passphrase, coin_type, self.next);
+        if wallet.fingerprint() != self.fingerprint {
+            return Err("wrong passphrase for the keystore".to_string());
+        }
+        Ok(wallet)
+    }
+}
+
+#[cfg(any(test, test_utilities))]
+mod tests {
+    use super::*;
+    use ring::signature::KeyPair;
+    use crate::crypto::batch::DirectVerifier;
+    use crate::transaction::coin_base;
+
+    #[test]
+    fn restores_from_mnemonic() {
+        let mut wallet = HdWallet::generate(1);
+        let receive: Vec<H160> = (0..3).map(|_| wallet.receive_address()).collect();
+        let change = wallet.change_address();
+        assert_eq!(wallet.addresses().len(), 4);
+        assert!(!receive.contains(&change));
+        let key = wallet.key_pair(&receive[1]).unwrap();
+        assert_eq!(H160::hash(key.public_key().as_ref()), receive[1]);
+
+        let phrase = wallet.mnemonic().phrase();
+        let mut restored = HdWallet::from_mnemonic(Mnemonic::parse(&phrase).unwrap(), "", 1);
+        let again: Vec<H160> = (0..3).map(|_| restored.receive_address()).collect();
+        assert_eq!(again, receive);
+        assert_eq!(restored.change_address(), change);
+
+        // another passphrase or coin type is another wallet
+        let mut other = HdWallet::from_mnemonic(Mnemonic::parse(&phrase).unwrap(), "secret", 1);
+        assert_ne!(other.receive_address(), receive[0]);
+        let mut mainnet = HdWallet::from_mnemonic(Mnemonic::parse(&phrase).unwrap(), "", 0);
+        assert_ne!(mainnet.receive_address(), receive[0]);
+        assert!(wallet.key_pair(&[3u8; 20].into()).is_none());
+    }
+
+    #[test]
+    fn restores_indices() {
+        let mut wallet = HdWallet::generate(1);
+        let receive: Vec<H160> = (0..2).map(|_| wallet.receive_address()).collect();
+        wallet.change_address();
+        let phrase = wallet.mnemonic().phrase();
+        let mut restored = HdWallet::restore(Mnemonic::parse(&phrase).unwrap(), "", 1, wallet.next_indices());
+        assert_eq!(restored.next_indices(), [2, 1]);
+        assert!(restored.key_pair(&receive[1]).is_some());
+        assert_eq!(restored.receive_address(), wallet.receive_address());
+    }
+
+    #[test]
+    fn keystore_round_trip() {
+        let coin_type = ChainParams::for_network(Network::Regtest).coin_type;
+        let mut wallet = HdWallet::from_mnemonic(Mnemonic::generate(12).unwrap(), "secret", coin_type);
+        let address = wallet.receive_address();
+        let keystore = Keystore::new(Network::Regtest, &wallet, true);
+        let path = std::env::temp_dir().join(format!("keystore-{}.json", address));
+        keystore.save(&path).unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(!json.contains("secret"));
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions()) & 0o777, 0o600);
+
+        let loaded = Keystore::load(&path, Network::Regtest).unwrap();
+        assert!(Keystore::load(&path, Network::Mainnet).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, keystore);
+        assert!(loaded.wallet("secret").unwrap().key_pair(&address).is_some());
+        assert!(loaded.wallet("").is_err());
+        assert!(loaded.wallet("secreT").is_err());
+    }
+
+    #[test]
+    fn creates_transaction() {
+        let mut wallet = HdWallet::generate(1);
+        let funding = vec![coin_base(&wallet.receive_address(), 6, 1), coin_base(&wallet.receive_address(), 4, 2)];
+        let to: H160 = [9u8; 20].into();
+        assert!(wallet.create_transaction(&funding, to, 11).is_err());
+
+        let tx = wallet.create_transaction(&funding, to, 8).unwrap();
+        assert_eq!(tx.transaction.inputs.len(), 2);
+        assert_eq!((tx.transaction.input_val(), tx.transaction.output_val()), (10, 10));
+        assert_eq!(tx.transaction.outputs[0].address, to);
+        assert_eq!(tx.transaction.outputs[1].balance, 2);
+        let spent = |id: &crate::crypto::hash::H256| funding.iter()
+            .find(|f| f.id == *id)
+            .map(|f| (f.outputs.clone(), 1));
+        tx.verify(spent, 3, &DirectVerifier).unwrap();
+        assert!(tx.signature_checks(spent).iter().all(|check| check.verify()));
+
+        // someone else's outputs cannot be spent
+        let mut other = HdWallet::generate(1);
+        assert!(other.create_transaction(&funding, to, 1).is_err());
+    }
+}